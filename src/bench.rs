@@ -0,0 +1,56 @@
+use std::{str::FromStr, time::Instant};
+
+use chess::Board;
+
+use crate::{
+    helpers::halfmove_clock,
+    search::{Engine, HistoryEntry, SearchLimits},
+};
+
+/// A mix of opening, middlegame and endgame positions, so changes to any part of the search or
+/// evaluation show up in the node count
+const BENCH_POSITIONS: &[&str] = &[
+    "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+    "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+    "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+    "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+    "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+    "r1bqk2r/pppp1ppp/2n2n2/2b1p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 4",
+    "r1bq1rk1/pp2bppp/2n1pn2/3p4/2PP4/2N2NP1/PP2PPBP/R1BQ1RK1 w - - 0 9",
+    "2rq1rk1/pb1nbppp/1p2pn2/2pp4/3P4/1P2PNP1/PBPN1PBP/R2Q1RK1 w - - 0 11",
+    "r2qr1k1/1p1nbppp/p2p1n2/2pP4/P3P3/2N2N2/1P2BPPP/R2QR1K1 w - - 3 14",
+    "6k1/5ppp/8/8/8/8/5PPP/3R2K1 w - - 0 1",
+    "8/8/4k3/3p4/3P4/4K3/8/8 w - - 0 1",
+    "8/5pk1/6p1/7p/7P/6P1/5PK1/3R4 w - - 0 1",
+    "4r1k1/p4ppp/1p6/2p5/2P1n3/1P2B2P/P4PP1/3R2K1 b - - 0 25",
+    "2k5/8/1pP1K3/1P6/8/8/8/8 w - - 0 1",
+];
+
+/// Search every bench position to `depth`, then print how many nodes that took and how fast
+pub fn run_bench(depth: u8, hash_mb: usize) {
+    let mut engine = Engine::new(hash_mb);
+    engine.report = false;
+    let mut nodes = 0;
+    let start = Instant::now();
+
+    for (i, fen) in BENCH_POSITIONS.iter().enumerate() {
+        println!("Position {}/{}: {}", i + 1, BENCH_POSITIONS.len(), fen);
+        let board = Board::from_str(fen).expect("Invalid bench position");
+        engine.new_game();
+        engine.best_move(
+            board,
+            &[HistoryEntry::new(&board, halfmove_clock(fen))],
+            SearchLimits::depth(depth),
+        );
+        nodes += engine.total_nodes();
+    }
+
+    let elapsed = start.elapsed();
+    println!();
+    println!("Total time (ms): {}", elapsed.as_millis());
+    println!("Nodes searched: {}", nodes);
+    println!(
+        "Nodes/second: {}",
+        (nodes as f64 / elapsed.as_secs_f64().max(1e-9)) as u64
+    );
+}
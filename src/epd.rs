@@ -0,0 +1,211 @@
+use std::{fs, str::FromStr};
+
+use chess::{Board, ChessMove};
+
+use crate::{
+    san::{parse_san, to_san},
+    search::{Engine, HistoryEntry, SearchLimits},
+    time_manager::{TimeManager, DEFAULT_MOVE_OVERHEAD},
+};
+
+/// A test position from an EPD file, with the opcodes we understand
+pub struct EpdEntry {
+    pub board: Board,
+    pub halfmove_clock: u16,
+    pub id: Option<String>,
+    /// `bm`: any of these moves passes
+    pub best_moves: Vec<ChessMove>,
+    /// `am`: any of these moves fails
+    pub avoid_moves: Vec<ChessMove>,
+    /// STS style `c0` weights, like `c0 "Nf3=10, e4=5"`
+    pub points: Vec<(ChessMove, u32)>,
+}
+
+/// Split EPD operations on semicolons, except those in quoted strings
+fn split_operations(operations: &str) -> Vec<&str> {
+    let mut result = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    for (i, c) in operations.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ';' if !quoted => {
+                result.push(&operations[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    result.push(&operations[start..]);
+    result
+        .into_iter()
+        .map(str::trim)
+        .filter(|op| !op.is_empty())
+        .collect()
+}
+
+fn parse_moves(board: &Board, operands: &str) -> Result<Vec<ChessMove>, String> {
+    operands
+        .split_whitespace()
+        .map(|san| parse_san(board, san).ok_or_else(|| format!("Invalid move: {}", san)))
+        .collect()
+}
+
+pub fn parse_epd(line: &str) -> Result<EpdEntry, String> {
+    let mut fields = line.split_whitespace();
+    let position: Vec<&str> = fields.by_ref().take(4).collect();
+    if position.len() < 4 {
+        return Err("Expected four FEN fields".to_string());
+    }
+    let rest = fields.collect::<Vec<_>>().join(" ");
+    let operations = split_operations(&rest);
+
+    let mut halfmove_clock = 0;
+    let mut fullmove_number = 1;
+    for op in &operations {
+        match op.split_once(' ') {
+            Some(("hmvc", n)) => halfmove_clock = n.trim().parse().map_err(|_| "Invalid hmvc")?,
+            Some(("fmvn", n)) => fullmove_number = n.trim().parse().map_err(|_| "Invalid fmvn")?,
+            _ => {}
+        }
+    }
+    let fen = format!(
+        "{} {} {}",
+        position.join(" "),
+        halfmove_clock,
+        fullmove_number
+    );
+    let board = Board::from_str(&fen).map_err(|_| format!("Invalid position: {}", fen))?;
+
+    let mut entry = EpdEntry {
+        board,
+        halfmove_clock,
+        id: None,
+        best_moves: Vec::new(),
+        avoid_moves: Vec::new(),
+        points: Vec::new(),
+    };
+    for op in operations {
+        let (opcode, operands) = op.split_once(' ').unwrap_or((op, ""));
+        let operands = operands.trim();
+        match opcode {
+            "bm" => entry.best_moves = parse_moves(&board, operands)?,
+            "am" => entry.avoid_moves = parse_moves(&board, operands)?,
+            "id" => entry.id = Some(operands.trim_matches('"').to_string()),
+            "c0" => {
+                // Other suites use c0 for plain comments, so only take what looks like weights
+                entry.points = operands
+                    .trim_matches('"')
+                    .split(',')
+                    .filter_map(|weight| {
+                        let (san, points) = weight.trim().split_once('=')?;
+                        Some((parse_san(&board, san)?, points.trim().parse().ok()?))
+                    })
+                    .collect();
+            }
+            _ => {}
+        }
+    }
+    Ok(entry)
+}
+
+/// Search every position in the EPD file at `path`, and report which ones the engine solves.
+/// `depth`, `nodes` and `movetime` limit each search.
+pub fn run_epd(
+    path: &str,
+    depth: u8,
+    nodes: Option<usize>,
+    movetime: Option<u64>,
+    hash_mb: usize,
+) -> Result<(), String> {
+    let contents =
+        fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    let mut engine = Engine::new(hash_mb);
+    engine.report = false;
+
+    let (mut total, mut passed) = (0, 0);
+    let (mut points, mut max_points) = (0, 0);
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let entry = match parse_epd(line) {
+            Ok(entry) => entry,
+            Err(e) => {
+                println!("Line {}: {}", i + 1, e);
+                continue;
+            }
+        };
+        let id = entry
+            .id
+            .clone()
+            .unwrap_or_else(|| format!("line {}", i + 1));
+
+        // The clock starts when the limits are created, so make them for every position
+        let limits = SearchLimits {
+            depth,
+            nodes,
+            time: movetime.map_or_else(TimeManager::infinite, |movetime| {
+                TimeManager::from_movetime(movetime, DEFAULT_MOVE_OVERHEAD)
+            }),
+        };
+        engine.new_game();
+        let history = [HistoryEntry::new(&entry.board, entry.halfmove_clock)];
+        let best_move = engine
+            .best_move(entry.board, &history, limits)
+            .map(|r| r.best_move());
+
+        let position_points = entry
+            .points
+            .iter()
+            .find(|(m, _)| Some(*m) == best_move)
+            .map_or(0, |&(_, p)| p);
+        let ok = match best_move {
+            Some(_) if entry.best_moves.is_empty() && entry.avoid_moves.is_empty() => {
+                position_points > 0 || entry.points.is_empty()
+            }
+            Some(m) => {
+                (entry.best_moves.is_empty() || entry.best_moves.contains(&m))
+                    && !entry.avoid_moves.contains(&m)
+            }
+            None => false,
+        };
+
+        total += 1;
+        passed += ok as usize;
+        points += position_points;
+        max_points += entry.points.iter().map(|&(_, p)| p).max().unwrap_or(0);
+
+        let expected = |moves: &[ChessMove]| {
+            moves
+                .iter()
+                .map(|&m| to_san(&entry.board, m))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        let mut report = format!(
+            "{}: {} {}",
+            id,
+            if ok { "PASS" } else { "FAIL" },
+            best_move.map_or("(none)".to_string(), |m| to_san(&entry.board, m))
+        );
+        if !entry.best_moves.is_empty() {
+            report += &format!("; bm {}", expected(&entry.best_moves));
+        }
+        if !entry.avoid_moves.is_empty() {
+            report += &format!("; am {}", expected(&entry.avoid_moves));
+        }
+        if !entry.points.is_empty() {
+            report += &format!("; points {}", position_points);
+        }
+        println!("{}", report);
+    }
+
+    println!();
+    println!("Passed: {}/{}", passed, total);
+    if max_points > 0 {
+        println!("Points: {}/{}", points, max_points);
+    }
+    Ok(())
+}
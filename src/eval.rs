@@ -1,10 +1,61 @@
-use chess::{Board, BoardStatus, ChessMove, Color, Piece};
+use std::ops::{Add, AddAssign, Mul, Neg, Sub};
+
+use chess::{Board, Color, Piece, ALL_PIECES};
 
 use crate::{
-    helpers::{color_to_num, N_INF},
+    helpers::color_to_num,
+    king_safety::evaluate_king_safety,
+    pawns::{evaluate_pawns, PawnTable},
+    pieces::evaluate_pieces,
+    pst::psqt,
     score::ScoreTy,
 };
 
+/// A middlegame and an endgame score, blended together by the game phase
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Tapered(pub i32, pub i32);
+
+impl Add for Tapered {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self {
+        Tapered(self.0 + rhs.0, self.1 + rhs.1)
+    }
+}
+
+impl AddAssign for Tapered {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+
+impl Sub for Tapered {
+    type Output = Self;
+    fn sub(self, rhs: Self) -> Self {
+        Tapered(self.0 - rhs.0, self.1 - rhs.1)
+    }
+}
+
+impl Neg for Tapered {
+    type Output = Self;
+    fn neg(self) -> Self {
+        Tapered(-self.0, -self.1)
+    }
+}
+
+impl Mul<i32> for Tapered {
+    type Output = Self;
+    fn mul(self, rhs: i32) -> Self {
+        Tapered(self.0 * rhs, self.1 * rhs)
+    }
+}
+
+impl Tapered {
+    #[inline(always)]
+    pub fn taper(self, phase: i32) -> i32 {
+        (self.0 * phase + self.1 * (MAX_PHASE - phase)) / MAX_PHASE
+    }
+}
+
 #[inline(always)]
 fn count_piece(board: Board, piece: Piece, color: Color) -> ScoreTy {
     let ps = board.pieces(piece);
@@ -39,53 +90,60 @@ fn material(board: Board, color: Color) -> ScoreTy {
     queen_s + rook_s + bishop_s + knight_s + pawn_s
 }
 
-const ENDGAME_MAT: ScoreTy = 1300;
+/// Phase of the starting position, going down to 0 as pieces are traded
+pub const MAX_PHASE: i32 = 24;
 
+/// How far the game is from the endgame, based on the non-pawn material left
 #[inline(always)]
-pub fn is_endgame(board: Board) -> bool {
-    material(board, board.side_to_move()) < ENDGAME_MAT
+pub fn game_phase(board: &Board) -> i32 {
+    let count = |piece| board.pieces(piece).popcnt() as i32;
+    let phase = count(Piece::Knight)
+        + count(Piece::Bishop)
+        + 2 * count(Piece::Rook)
+        + 4 * count(Piece::Queen);
+    phase.min(MAX_PHASE)
 }
 
-const ROOK_PAIR: ScoreTy = -16;
-const KNIGHT_PAIR: ScoreTy = -8;
-const BISHOP_PAIR: ScoreTy = 30;
+/// Whether `color` has anything but pawns and a king. If not, zugzwang is likely.
+#[inline(always)]
+pub fn has_non_pawn_material(board: &Board, color: Color) -> bool {
+    let pawns_and_king = board.pieces(Piece::Pawn) | board.pieces(Piece::King);
+    (board.color_combined(color) & !pawns_and_king).0 != 0
+}
 
 #[inline(always)]
-fn pairs(board: Board, color: Color) -> ScoreTy {
-    count_piece(board, Piece::Bishop, color) % 2 * BISHOP_PAIR
-        + count_piece(board, Piece::Knight, color) % 2 * KNIGHT_PAIR
-        + count_piece(board, Piece::Rook, color) % 2 * ROOK_PAIR
+fn piece_squares(board: &Board, color: Color) -> Tapered {
+    let mut score = Tapered::default();
+    for &piece in ALL_PIECES.iter() {
+        for square in board.pieces(piece) & board.color_combined(color) {
+            score += psqt(piece, color, square);
+        }
+    }
+    score
 }
 
-const MOBILITY_WT: ScoreTy = 1;
+const ROOK_PAIR: ScoreTy = -16;
+const KNIGHT_PAIR: ScoreTy = -8;
+const BISHOP_PAIR: ScoreTy = 30;
 
 #[inline(always)]
-#[allow(deprecated)]
-fn mobility(board: Board, color: Color) -> ScoreTy {
-    let new_board = if board.side_to_move() == color {
-        Some(board)
-    } else {
-        board.null_move()
-    };
-
-    return (new_board
-        .map(|b| b.enumerate_moves(&mut [ChessMove::default(); 256]))
-        .unwrap_or(20) as ScoreTy)
-        / 3
-        * MOBILITY_WT;
+fn pairs(board: Board, color: Color) -> ScoreTy {
+    let has_pair = |piece| (count_piece(board, piece, color) >= 2) as ScoreTy;
+    has_pair(Piece::Bishop) * BISHOP_PAIR
+        + has_pair(Piece::Knight) * KNIGHT_PAIR
+        + has_pair(Piece::Rook) * ROOK_PAIR
 }
 
+/// Statically evaluate `board` from the side to move's perspective. Checkmate and stalemate aren't
+/// detected here, since that needs move generation; the search handles them.
 #[inline(always)]
-pub fn evaluate(board: Board) -> ScoreTy {
-    match board.status() {
-        BoardStatus::Ongoing => {
-            let material_delta = material(board, Color::White) - material(board, Color::Black);
-            let pairs_delta = pairs(board, Color::White) - pairs(board, Color::Black);
-            let mobilty_delta = mobility(board, Color::White) - mobility(board, Color::Black);
-            let score = material_delta + pairs_delta + mobilty_delta;
-            score * color_to_num(board.side_to_move())
-        }
-        BoardStatus::Checkmate => N_INF + 1,
-        BoardStatus::Stalemate => 0,
-    }
+pub fn evaluate(board: Board, pawns: &mut PawnTable) -> ScoreTy {
+    let material_delta = material(board, Color::White) - material(board, Color::Black);
+    let pairs_delta = pairs(board, Color::White) - pairs(board, Color::Black);
+    let positional = piece_squares(&board, Color::White) - piece_squares(&board, Color::Black)
+        + evaluate_pawns(&board, pawns)
+        + evaluate_king_safety(&board)
+        + evaluate_pieces(&board);
+    let score = material_delta as i32 + pairs_delta as i32 + positional.taper(game_phase(&board));
+    score as ScoreTy * color_to_num(board.side_to_move())
 }
@@ -1,6 +1,14 @@
 use clap::Parser;
 
-const VERSION: &'static str = env!("CARGO_PKG_VERSION");
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Counts that need to be positive, like the UCI options with a minimum of 1
+fn at_least_one(value: &str) -> Result<(), String> {
+    match value.parse::<usize>() {
+        Ok(0) => Err("must be at least 1".to_string()),
+        _ => Ok(()),
+    }
+}
 
 #[derive(Parser)]
 #[clap(version = VERSION, about = "The crabfish chess engine.")]
@@ -16,6 +24,18 @@ pub enum SubCommand {
 
     #[clap(about = "The UCI engine protocol")]
     Uci,
+
+    #[clap(about = "Count the leaf nodes of the move tree, to verify move generation")]
+    Perft(Perft),
+
+    #[clap(about = "Search a fixed set of positions, to compare node counts and speed")]
+    Bench(Bench),
+
+    #[clap(about = "Run a test suite of EPD positions with best or avoid moves")]
+    Epd(Epd),
+
+    #[clap(about = "Play a match between two UCI engines, and test the result with an SPRT")]
+    Match(Match),
 }
 
 #[derive(Parser)]
@@ -24,21 +44,253 @@ pub struct Move {
         short,
         long,
         about = "An FEN string. Will read from stdin if not provided",
-        conflicts_with = "interactive"
+        conflicts_with_all = &["interactive", "pgn"]
     )]
     pub fen: Option<String>,
 
-    #[clap(short, long, about = "Interactive mode", conflicts_with = "fen")]
+    #[clap(
+        short,
+        long,
+        about = "Interactive mode",
+        conflicts_with_all = &["fen", "pgn"]
+    )]
     pub interactive: bool,
 
-    #[clap(short, long, about = "Max depth of search", default_value = "9")]
+    #[clap(long, about = "A PGN file to take the position from")]
+    pub pgn: Option<String>,
+
+    #[clap(
+        long,
+        about = "Which game in the PGN file to use, starting from 1",
+        default_value = "1"
+    )]
+    pub game: usize,
+
+    #[clap(
+        long,
+        about = "Number of moves of the PGN game to play before searching. Defaults to all of them"
+    )]
+    pub ply: Option<usize>,
+
+    #[clap(
+        short,
+        long,
+        about = "Max depth of search. Defaults to 9, or no limit with --nodes or --movetime"
+    )]
+    pub depth: Option<u8>,
+
+    #[clap(short, long, about = "Maximum number of nodes to search")]
+    pub nodes: Option<usize>,
+
+    #[clap(short = 't', long, about = "Maximum time to search, in milliseconds")]
+    pub movetime: Option<u64>,
+
+    #[clap(
+        long,
+        about = "Number of search threads",
+        default_value = "1",
+        validator = at_least_one
+    )]
+    pub threads: usize,
+
+    #[clap(
+        long,
+        about = "Number of best lines to search and report",
+        default_value = "1",
+        validator = at_least_one
+    )]
+    pub multipv: usize,
+
+    #[clap(
+        short,
+        long,
+        about = "Score of a draw in centipawns, from the opponent's point of view",
+        default_value = "0"
+    )]
+    pub contempt: i16,
+
+    #[clap(
+        long,
+        about = "Size of the transposition table, in megabytes",
+        default_value = "64"
+    )]
+    pub hash: usize,
+}
+
+#[derive(Parser)]
+pub struct Perft {
+    #[clap(
+        short,
+        long,
+        about = "An FEN string. Uses the starting position if not provided"
+    )]
+    pub fen: Option<String>,
+
+    #[clap(short, long, about = "Depth of the move tree", default_value = "5")]
     pub depth: u8,
 
+    #[clap(long, about = "Show the node count below each root move")]
+    pub divide: bool,
+}
+
+#[derive(Parser)]
+pub struct Bench {
     #[clap(
         short,
         long,
-        about = "Size of the transposition table. Must be power of 2",
-        default_value = "33554432"
+        about = "Depth to search each position to",
+        default_value = "9"
     )]
-    pub memo: usize,
+    pub depth: u8,
+
+    #[clap(
+        long,
+        about = "Size of the transposition table, in megabytes",
+        default_value = "16"
+    )]
+    pub hash: usize,
+}
+
+#[derive(Parser)]
+pub struct Epd {
+    #[clap(about = "Path to the EPD file")]
+    pub file: String,
+
+    #[clap(short, long, about = "Max depth of search for each position")]
+    pub depth: Option<u8>,
+
+    #[clap(
+        short,
+        long,
+        about = "Maximum number of nodes to search for each position"
+    )]
+    pub nodes: Option<usize>,
+
+    #[clap(
+        short = 't',
+        long,
+        about = "Maximum time to search each position, in milliseconds"
+    )]
+    pub movetime: Option<u64>,
+
+    #[clap(
+        long,
+        about = "Size of the transposition table, in megabytes",
+        default_value = "16"
+    )]
+    pub hash: usize,
+}
+
+#[derive(Parser)]
+pub struct Match {
+    #[clap(
+        long,
+        about = "Command to start the first engine. Defaults to this binary"
+    )]
+    pub engine_a: Option<String>,
+
+    #[clap(
+        long,
+        about = "Command to start the second engine. Defaults to this binary"
+    )]
+    pub engine_b: Option<String>,
+
+    #[clap(
+        long,
+        multiple_occurrences = true,
+        about = "A UCI option for the first engine, as name=value. Can be repeated"
+    )]
+    pub option_a: Vec<String>,
+
+    #[clap(
+        long,
+        multiple_occurrences = true,
+        about = "A UCI option for the second engine, as name=value. Can be repeated"
+    )]
+    pub option_b: Vec<String>,
+
+    #[clap(short, long, about = "Maximum number of games", default_value = "100")]
+    pub games: usize,
+
+    #[clap(
+        long,
+        about = "A file of FEN or EPD opening positions, or a .pgn file of opening lines"
+    )]
+    pub openings: Option<String>,
+
+    #[clap(
+        short = 't',
+        long,
+        about = "Time per move, in milliseconds. Ignored if --time is given",
+        default_value = "100"
+    )]
+    pub movetime: u64,
+
+    #[clap(long, about = "Time per game for each side, in milliseconds")]
+    pub time: Option<u64>,
+
+    #[clap(
+        long,
+        about = "Increment per move, in milliseconds",
+        default_value = "0"
+    )]
+    pub inc: u64,
+
+    #[clap(long, about = "File to append the games to, as PGN")]
+    pub pgn: Option<String>,
+
+    #[clap(
+        long,
+        about = "Score in centipawns at which a side resigns",
+        default_value = "1000"
+    )]
+    pub resign_score: i32,
+
+    #[clap(
+        long,
+        about = "Number of moves in a row a side must be lost for to resign",
+        default_value = "3"
+    )]
+    pub resign_moves: usize,
+
+    #[clap(
+        long,
+        about = "Score in centipawns both sides must be within for a draw",
+        default_value = "10"
+    )]
+    pub draw_score: i32,
+
+    #[clap(
+        long,
+        about = "Number of moves in a row each side must be drawn for",
+        default_value = "8"
+    )]
+    pub draw_moves: usize,
+
+    #[clap(
+        long,
+        about = "First move number at which a draw can be adjudicated",
+        default_value = "40"
+    )]
+    pub draw_start: usize,
+
+    #[clap(
+        long,
+        about = "Elo difference of the null hypothesis",
+        default_value = "0"
+    )]
+    pub elo0: f64,
+
+    #[clap(
+        long,
+        about = "Elo difference of the alternative hypothesis",
+        default_value = "5"
+    )]
+    pub elo1: f64,
+
+    #[clap(long, about = "False positive rate", default_value = "0.05")]
+    pub alpha: f64,
+
+    #[clap(long, about = "False negative rate", default_value = "0.05")]
+    pub beta: f64,
 }
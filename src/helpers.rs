@@ -1,4 +1,4 @@
-use chess::{Board, BoardStatus, Color};
+use chess::{Board, BoardStatus, ChessMove, Color, Piece};
 
 use crate::score::ScoreTy;
 
@@ -10,6 +10,16 @@ pub fn game_over(board: Board) -> bool {
     }
 }
 
+/// The piece `m` captures, counting en passant, where the destination square is empty
+#[inline(always)]
+pub fn captured_piece(board: &Board, m: ChessMove) -> Option<Piece> {
+    board.piece_on(m.get_dest()).or_else(|| {
+        let diagonal_pawn_move = board.piece_on(m.get_source()) == Some(Piece::Pawn)
+            && m.get_source().get_file() != m.get_dest().get_file();
+        diagonal_pawn_move.then_some(Piece::Pawn)
+    })
+}
+
 #[inline(always)]
 pub fn color_to_num(color: Color) -> ScoreTy {
     match color {
@@ -18,5 +28,20 @@ pub fn color_to_num(color: Color) -> ScoreTy {
     }
 }
 
+/// The halfmove clock field of a FEN string, or 0 if it is missing
+pub fn halfmove_clock(fen: &str) -> u16 {
+    fen.split_whitespace()
+        .nth(4)
+        .and_then(|clock| clock.parse().ok())
+        .unwrap_or(0)
+}
+
+pub fn format_pv(pv: &[ChessMove]) -> String {
+    pv.iter()
+        .map(|m| m.to_string())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
 pub const N_INF: ScoreTy = ScoreTy::MIN + 1;
 pub const P_INF: ScoreTy = ScoreTy::MAX;
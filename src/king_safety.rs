@@ -0,0 +1,101 @@
+use chess::{
+    get_bishop_moves, get_file, get_king_moves, get_knight_moves, get_rook_moves, BitBoard, Board,
+    Color, File, Piece, Square,
+};
+
+use crate::{
+    eval::Tapered,
+    pawns::{forward_ranks, relative_rank},
+};
+
+/// Attack units per king zone square attacked, indexed by piece
+const ATTACK_WEIGHT: [i32; 6] = [0, 2, 2, 3, 5, 0];
+const MAX_ATTACK_PENALTY: i32 = 500;
+
+/// Indexed by how far the closest friendly pawn is in front of the king, 0 if there is none
+const SHELTER: [Tapered; 4] = [
+    Tapered(-30, 0),
+    Tapered(0, 0),
+    Tapered(-12, 0),
+    Tapered(-22, 0),
+];
+const OPEN_FILE: Tapered = Tapered(-25, 0);
+const SEMI_OPEN_FILE: Tapered = Tapered(-12, 0);
+
+/// The king's square and its neighbours, plus the squares one further rank in front of it
+#[inline(always)]
+fn king_zone(king: Square, color: Color) -> BitBoard {
+    let around = get_king_moves(king) | BitBoard::from_square(king);
+    let front = around & forward_ranks(color, king.get_rank());
+    around
+        | match color {
+            Color::White => BitBoard(front.0 << 8),
+            Color::Black => BitBoard(front.0 >> 8),
+        }
+}
+
+/// Penalty for the enemy pieces that attack the zone around `color`'s king
+fn attacks(board: &Board, color: Color, king: Square) -> Tapered {
+    let zone = king_zone(king, color);
+    let occupied = *board.combined();
+    let enemy = board.color_combined(!color);
+
+    let mut attackers = 0;
+    let mut units = 0;
+    for piece in [Piece::Knight, Piece::Bishop, Piece::Rook, Piece::Queen] {
+        for square in board.pieces(piece) & enemy {
+            let targets = match piece {
+                Piece::Knight => get_knight_moves(square),
+                Piece::Bishop => get_bishop_moves(square, occupied),
+                Piece::Rook => get_rook_moves(square, occupied),
+                _ => get_bishop_moves(square, occupied) | get_rook_moves(square, occupied),
+            } & zone;
+            if targets != BitBoard(0) {
+                attackers += 1;
+                units += ATTACK_WEIGHT[piece.to_index()] * targets.popcnt() as i32;
+            }
+        }
+    }
+
+    // A lone attacker is rarely dangerous
+    if attackers < 2 {
+        return Tapered::default();
+    }
+    Tapered(-(units * units / 4).min(MAX_ATTACK_PENALTY), 0)
+}
+
+/// Penalty for missing pawns and open files on and next to the king's file
+fn shelter(board: &Board, color: Color, king: Square) -> Tapered {
+    let pawns = board.pieces(Piece::Pawn);
+    let own = pawns & board.color_combined(color);
+    let ahead = forward_ranks(color, king.get_rank());
+
+    // Keep three files even when the king is on the edge of the board
+    let center = king.get_file().to_index().clamp(1, 6);
+    let mut score = Tapered::default();
+    for file in (center - 1..=center + 1).map(File::from_index) {
+        let file_mask = get_file(file);
+        let shield = (own & file_mask & ahead)
+            .map(|sq| relative_rank(color, sq) - relative_rank(color, king))
+            .min()
+            .unwrap_or(0);
+        score += SHELTER[shield.min(SHELTER.len() - 1)];
+
+        if pawns & file_mask == BitBoard(0) {
+            score += OPEN_FILE;
+        } else if own & file_mask == BitBoard(0) {
+            score += SEMI_OPEN_FILE;
+        }
+    }
+    score
+}
+
+/// King safety from white's point of view. Only middlegame terms are used, so it fades out as
+/// pieces come off the board.
+pub fn evaluate_king_safety(board: &Board) -> Tapered {
+    let side = |color| {
+        let king = board.king_square(color);
+        attacks(board, color, king) + shelter(board, color, king)
+    };
+    side(Color::White) - side(Color::Black)
+}
@@ -1,17 +1,35 @@
+mod bench;
+mod epd;
 mod eval;
 mod flags;
 mod helpers;
+mod king_safety;
+mod match_runner;
 mod move_sort;
+mod options;
+mod pawns;
+mod perft;
+mod pgn;
+mod pieces;
+mod pst;
+mod san;
 mod score;
 mod search;
+mod see;
+mod sprt;
+mod time_manager;
 mod transposition;
 
+use chess::{Board, ChessMove, Color};
 use clap::Parser;
-use chess::{Board, ChessMove};
-use helpers::game_over;
+use helpers::{format_pv, game_over, halfmove_clock};
+use score::format_score;
+use search::{HistoryEntry, SearchLimits};
+use time_manager::{TimeManager, DEFAULT_MOVE_OVERHEAD};
+use transposition::DEFAULT_HASH_MB;
 
 use std::{
-    io,
+    fs, io,
     io::BufRead,
     mem,
     process::exit,
@@ -21,28 +39,64 @@ use std::{
         Arc, Mutex,
     },
     thread,
+    time::Duration,
 };
 
 pub static TIME_UP: AtomicBool = AtomicBool::new(false);
+/// Set during `go ponder`, until `ponderhit` or `stop`
+pub static PONDERING: AtomicBool = AtomicBool::new(false);
 
 #[derive(Default, Debug)]
 pub struct UciConfig {
     ponder: bool,
-    wtime: i64,
-    btime: i64,
+    wtime: Option<u64>,
+    btime: Option<u64>,
+    winc: u64,
+    binc: u64,
+    movestogo: Option<u64>,
     nodes: Option<usize>,
     depth: Option<u8>,
     movetime: Option<u64>,
     infinite: bool,
+    perft: Option<u8>,
 }
 
-fn eval_from_fen(engine: &mut search::Engine, depth: u8, fen: &str) -> bool {
-    let board = Board::from_str(&fen).expect("Invalid FEN position");
+/// Clock values can be negative when a GUI lets the engine overstep its time
+fn parse_clock(value: &str) -> u64 {
+    value.parse::<i64>().unwrap().max(0) as u64
+}
+
+fn eval_from_fen(engine: &mut search::Engine, limits: SearchLimits, fen: &str) -> bool {
+    let board = Board::from_str(fen).expect("Invalid FEN position");
+    eval_position(
+        engine,
+        limits,
+        board,
+        &[HistoryEntry::new(&board, halfmove_clock(fen))],
+    )
+}
+
+/// Search `board`, the last position in `history`, and print the result
+fn eval_position(
+    engine: &mut search::Engine,
+    limits: SearchLimits,
+    board: Board,
+    history: &[HistoryEntry],
+) -> bool {
     if game_over(board) {
         return true;
     }
-    let (best_move, eval) = engine.best_move(depth, board).unwrap();
-    println!("Best move: {}; Analysis: {}", best_move, eval);
+    // Nothing is searched at depth 0
+    let Some(result) = engine.best_move(board, history, limits) else {
+        println!("Best move: (none)");
+        return false;
+    };
+    println!(
+        "Best move: {}; Analysis: {}; PV: {}",
+        result.best_move(),
+        format_score(result.score),
+        format_pv(&result.pv)
+    );
 
     false
 }
@@ -53,137 +107,234 @@ fn main() {
     match conf.subcmd {
         flags::SubCommand::Uci => {
             let mut internal_board = Board::default();
-            let engine = Arc::new(Mutex::new(search::Engine::new(33554432)));
+            let mut internal_history = vec![HistoryEntry::new(&internal_board, 0)];
+            let engine = Arc::new(Mutex::new(search::Engine::new(DEFAULT_HASH_MB)));
             // SAFTEY: This is static because we never use the static reference in a way where the
             // value behind it would be dropped before other threads use it.
             let eng: &'static Arc<Mutex<search::Engine>> = unsafe { mem::transmute(&engine) };
             let mut joins = Vec::new();
-            loop {
-                if let Some(line) = io::stdin().lock().lines().next() {
-                    let input = line.expect("Failed to read from stdin");
-                    let mut items = input.split(" ");
-                    let cmd = items.next().unwrap();
-                    match cmd {
-                        "uci" => {
-                            println!("id name Crabfish {}", env!("CARGO_PKG_VERSION"));
-                            println!("id author Jonathan Li");
-                            println!("uciok");
-                        }
-                        "isready" => {
-                            println!("readyok");
+            while let Some(line) = io::stdin().lock().lines().next() {
+                let input = line.expect("Failed to read from stdin");
+                let mut items = input.split(' ');
+                let cmd = items.next().unwrap();
+                match cmd {
+                    "uci" => {
+                        println!("id name Crabfish {}", env!("CARGO_PKG_VERSION"));
+                        println!("id author Jonathan Li");
+                        options::print_options();
+                        println!("uciok");
+                    }
+                    "setoption" => match options::parse_setoption(items) {
+                        Some((name, value)) => {
+                            if let Err(e) =
+                                options::set_option(&mut eng.lock().unwrap(), &name, &value)
+                            {
+                                println!("info string {}", e);
+                            }
                         }
-                        "position" => {
-                            let mode = items.next().unwrap_or("");
-                            let mut board = if mode == "fen" {
-                                let mut fen = String::new();
-                                let mut next = items.next();
-                                while let Some(s) = next {
-                                    if s == "moves" {
-                                        break;
-                                    }
-                                    fen.push_str(s);
-                                    next = items.next();
+                        None => println!("info string Invalid setoption command"),
+                    },
+                    "ucinewgame" => {
+                        eng.lock().unwrap().new_game();
+                    }
+                    "isready" => {
+                        println!("readyok");
+                    }
+                    "position" => {
+                        let mode = items.next().unwrap_or("");
+                        let (mut board, clock) = if mode == "fen" {
+                            let mut fen = Vec::new();
+                            let mut next = items.next();
+                            while let Some(s) = next {
+                                if s == "moves" {
+                                    break;
                                 }
-                                Board::from_str(&fen).expect("Invalid FEN")
-                            } else if mode == "startpos" {
-                                // eat moves
-                                items.next();
-                                Board::default()
-                            } else {
-                                Board::default()
-                            };
-
-                            for cmove in items {
-                                board = board.make_move_new(
-                                    ChessMove::from_str(cmove).expect("invalid move"),
-                                );
+                                fen.push(s);
+                                next = items.next();
                             }
+                            let fen = fen.join(" ");
+                            (
+                                Board::from_str(&fen).expect("Invalid FEN"),
+                                halfmove_clock(&fen),
+                            )
+                        } else if mode == "startpos" {
+                            // eat moves
+                            items.next();
+                            (Board::default(), 0)
+                        } else {
+                            (Board::default(), 0)
+                        };
 
-                            internal_board = board;
+                        let mut history = vec![HistoryEntry::new(&board, clock)];
+                        for cmove in items {
+                            let m = ChessMove::from_str(cmove).expect("invalid move");
+                            let new_board = board.make_move_new(m);
+                            history.push(history.last().unwrap().after(&board, m, &new_board));
+                            board = new_board;
                         }
-                        "go" => {
-                            let mut config = UciConfig::default();
-                            while let Some(token) = items.next() {
-                                match token {
-                                    "movestogo" | "winc" | "binc" | "mate" => {
-                                        items.next().unwrap();
-                                    }
-                                    "ponder" => {
-                                        config.ponder = true;
-                                    }
-                                    "wtime" => {
-                                        config.wtime = items.next().unwrap().parse().unwrap();
-                                    }
-                                    "btime" => {
-                                        config.btime = items.next().unwrap().parse().unwrap();
-                                    }
-                                    "depth" => {
-                                        config.depth =
-                                            Some(items.next().unwrap().parse().unwrap());
-                                    }
-                                    "nodes" => {
-                                        config.nodes =
-                                            Some(items.next().unwrap().parse().unwrap());
-                                    }
-                                    "movetime" => {
-                                        config.movetime =
-                                            Some(items.next().unwrap().parse().unwrap());
-                                    }
-                                    "infinite" => {
-                                        config.infinite = true;
-                                    }
-                                    _ => {}
+
+                        internal_board = board;
+                        internal_history = history;
+                    }
+                    "go" => {
+                        let mut config = UciConfig::default();
+                        while let Some(token) = items.next() {
+                            match token {
+                                "mate" => {
+                                    items.next().unwrap();
+                                }
+                                "ponder" => {
+                                    config.ponder = true;
+                                }
+                                "wtime" => {
+                                    config.wtime = Some(parse_clock(items.next().unwrap()));
+                                }
+                                "btime" => {
+                                    config.btime = Some(parse_clock(items.next().unwrap()));
+                                }
+                                "winc" => {
+                                    config.winc = parse_clock(items.next().unwrap());
+                                }
+                                "binc" => {
+                                    config.binc = parse_clock(items.next().unwrap());
+                                }
+                                "movestogo" => {
+                                    config.movestogo = Some(items.next().unwrap().parse().unwrap());
+                                }
+                                "depth" => {
+                                    config.depth = Some(items.next().unwrap().parse().unwrap());
                                 }
+                                "nodes" => {
+                                    config.nodes = Some(items.next().unwrap().parse().unwrap());
+                                }
+                                "movetime" => {
+                                    config.movetime = Some(items.next().unwrap().parse().unwrap());
+                                }
+                                "infinite" => {
+                                    config.infinite = true;
+                                }
+                                "perft" => {
+                                    config.perft = Some(items.next().unwrap().parse().unwrap());
+                                }
+                                _ => {}
                             }
-                            let depth = if config.infinite {
-                                u8::MAX - 1
-                            } else {
-                                config.depth.unwrap_or(7)
-                            };
-                            dbg!(&config);
-                            joins.push(thread::spawn(move || {
-                                let (best_move, _) = Arc::clone(&eng)
-                                    .lock()
-                                    .unwrap()
-                                    .best_move(depth, internal_board)
-                                    .unwrap();
-                                println!("bestmove {}", best_move);
-                            }));
                         }
-                        "stop" => {
-                            TIME_UP.store(true, Ordering::SeqCst);
-                            for join in mem::take(&mut joins) {
-                                join.join().unwrap();
-                            }
+                        if let Some(depth) = config.perft {
+                            perft::run_perft(internal_board, depth, true);
+                            continue;
                         }
-                        "quit" => {
-                            TIME_UP.store(true, Ordering::SeqCst);
-                            for join in joins {
-                                join.join().unwrap();
+                        let (time_left, inc) = match internal_board.side_to_move() {
+                            Color::White => (config.wtime, config.winc),
+                            Color::Black => (config.btime, config.binc),
+                        };
+                        let overhead = eng.lock().unwrap().move_overhead;
+                        let time = match (config.movetime, time_left) {
+                            _ if config.infinite => TimeManager::infinite(),
+                            (Some(movetime), _) => TimeManager::from_movetime(movetime, overhead),
+                            (None, Some(time_left)) => {
+                                TimeManager::from_clock(time_left, inc, config.movestogo, overhead)
                             }
-                            exit(0);
+                            (None, None) => TimeManager::infinite(),
+                        };
+                        let unbounded = config.infinite
+                            || time_left.is_some()
+                            || config.movetime.is_some()
+                            || config.nodes.is_some();
+                        let limits = SearchLimits {
+                            depth: config
+                                .depth
+                                .unwrap_or(if unbounded { u8::MAX - 1 } else { 7 }),
+                            nodes: config.nodes,
+                            time,
+                        };
+                        TIME_UP.store(false, Ordering::SeqCst);
+                        PONDERING.store(config.ponder, Ordering::SeqCst);
+                        let history = internal_history.clone();
+                        let infinite = config.infinite;
+                        joins.push(thread::spawn(move || {
+                            let result = Arc::clone(eng).lock().unwrap().best_move(
+                                internal_board,
+                                &history,
+                                limits,
+                            );
+                            // The GUI doesn't expect a move before it ends the ponder or infinite
+                            // search, even if the search finished on its own
+                            while (infinite || PONDERING.load(Ordering::SeqCst))
+                                && !TIME_UP.load(Ordering::SeqCst)
+                            {
+                                thread::sleep(Duration::from_millis(1));
+                            }
+                            // With no legal moves or depth 0 there's no move, but the GUI still
+                            // needs an answer
+                            match result.as_ref().map(|r| (r.best_move(), r.pv.get(1))) {
+                                Some((best, Some(ponder))) => {
+                                    println!("bestmove {} ponder {}", best, ponder)
+                                }
+                                Some((best, None)) => println!("bestmove {}", best),
+                                None => println!("bestmove 0000"),
+                            }
+                        }));
+                    }
+                    "ponderhit" => {
+                        PONDERING.store(false, Ordering::SeqCst);
+                    }
+                    "stop" => {
+                        TIME_UP.store(true, Ordering::SeqCst);
+                        PONDERING.store(false, Ordering::SeqCst);
+                        for join in mem::take(&mut joins) {
+                            let _ = join.join();
                         }
-                        _ => {}
                     }
-                } else {
-                    break;
+                    "quit" => {
+                        TIME_UP.store(true, Ordering::SeqCst);
+                        for join in joins {
+                            let _ = join.join();
+                        }
+                        exit(0);
+                    }
+                    _ => {}
                 }
             }
         }
         flags::SubCommand::Move(conf) => {
-            let mut engine = search::Engine::new(conf.memo);
+            let mut engine = search::Engine::new(conf.hash);
+            engine.contempt = conf.contempt;
+            engine.multipv = conf.multipv;
+            engine.set_threads(conf.threads);
+            // The clock starts when the limits are created, so make them for every search
+            let (nodes, movetime) = (conf.nodes, conf.movetime);
+            let unbounded = nodes.is_some() || movetime.is_some();
+            let depth = conf
+                .depth
+                .unwrap_or(if unbounded { u8::MAX - 1 } else { 9 });
+            let limits = move || SearchLimits {
+                depth,
+                nodes,
+                time: movetime.map_or_else(TimeManager::infinite, |movetime| {
+                    TimeManager::from_movetime(movetime, DEFAULT_MOVE_OVERHEAD)
+                }),
+            };
             if conf.interactive {
-                loop {
-                    if let Some(line) = io::stdin().lock().lines().next() {
-                        let fen = line.expect("Failed to read from stdin");
-                        let game_over = eval_from_fen(&mut engine, conf.depth, &fen);
-                        if game_over {
-                            break;
-                        }
-                    } else {
+                while let Some(line) = io::stdin().lock().lines().next() {
+                    let fen = line.expect("Failed to read from stdin");
+                    let game_over = eval_from_fen(&mut engine, limits(), &fen);
+                    if game_over {
                         break;
                     }
                 }
+            } else if let Some(path) = conf.pgn {
+                let text = fs::read_to_string(&path).expect("Failed to read PGN file");
+                let games = pgn::parse_pgn(&text);
+                let game = games
+                    .get(conf.game.saturating_sub(1))
+                    .expect("No such game in the PGN file")
+                    .as_ref()
+                    .unwrap_or_else(|e| {
+                        eprintln!("Invalid PGN: {}", e);
+                        exit(1);
+                    });
+                let (board, history) = game.history(conf.ply.unwrap_or(game.moves.len()));
+                eval_position(&mut engine, limits(), board, &history);
             } else {
                 let fen = if let Some(fen) = conf.fen {
                     fen
@@ -196,7 +347,53 @@ fn main() {
                         .expect("Failed to read from stdin")
                 };
 
-                eval_from_fen(&mut engine, conf.depth, &fen);
+                eval_from_fen(&mut engine, limits(), &fen);
+            }
+        }
+        flags::SubCommand::Perft(conf) => {
+            let board = conf.fen.map_or_else(Board::default, |fen| {
+                Board::from_str(&fen).expect("Invalid FEN position")
+            });
+            perft::run_perft(board, conf.depth, conf.divide);
+        }
+        flags::SubCommand::Bench(conf) => bench::run_bench(conf.depth, conf.hash),
+        flags::SubCommand::Epd(conf) => {
+            // Without other limits, stop at a depth that's quick enough for a whole suite
+            let unbounded = conf.nodes.is_some() || conf.movetime.is_some();
+            let depth = conf
+                .depth
+                .unwrap_or(if unbounded { u8::MAX - 1 } else { 9 });
+            if let Err(e) = epd::run_epd(&conf.file, depth, conf.nodes, conf.movetime, conf.hash) {
+                eprintln!("{}", e);
+                exit(1);
+            }
+        }
+        flags::SubCommand::Match(conf) => {
+            let config = match_runner::MatchConfig {
+                engines: [conf.engine_a, conf.engine_b],
+                options: [conf.option_a, conf.option_b],
+                games: conf.games,
+                openings: conf.openings,
+                time_control: match conf.time {
+                    Some(time) => match_runner::TimeControl::Clock(time, conf.inc),
+                    None => match_runner::TimeControl::MoveTime(conf.movetime),
+                },
+                adjudication: match_runner::Adjudication {
+                    resign_score: conf.resign_score,
+                    resign_moves: conf.resign_moves,
+                    draw_score: conf.draw_score,
+                    draw_moves: conf.draw_moves,
+                    draw_start: conf.draw_start,
+                },
+                pgn: conf.pgn,
+                elo0: conf.elo0,
+                elo1: conf.elo1,
+                alpha: conf.alpha,
+                beta: conf.beta,
+            };
+            if let Err(e) = match_runner::run_match(config) {
+                eprintln!("{}", e);
+                exit(1);
             }
         }
     }
@@ -0,0 +1,555 @@
+use std::{
+    collections::HashMap,
+    env,
+    fs::{self, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    process::{Child, ChildStdin, Command, Stdio},
+    str::FromStr,
+    sync::mpsc::{self, Receiver, RecvTimeoutError},
+    thread,
+    time::{Duration, Instant},
+};
+
+use chess::{Board, BoardStatus, ChessMove, Color, Piece};
+
+use crate::{
+    epd::parse_epd,
+    pgn::{parse_pgn, Game},
+    sprt::{sprt, sprt_bounds, MatchScore, SprtResult},
+};
+
+/// Played from both sides when no opening file is given
+const DEFAULT_OPENINGS: &[&str] = &[
+    "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+    "r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3",
+    "rnbqkbnr/pp1ppppp/8/2p5/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 2",
+    "rnbqkbnr/pppp1ppp/4p3/8/3PP3/8/PPP2PPP/RNBQKBNR b KQkq - 0 2",
+    "rnbqkbnr/ppp1pppp/8/3p4/2PP4/8/PP2PPPP/RNBQKBNR b KQkq - 0 2",
+    "rnbqkb1r/pppppp1p/5np1/8/2PP4/8/PP2PPPP/RNBQKBNR w KQkq - 0 3",
+    "rnbqkbnr/pp2pppp/2p5/3p4/3PP3/8/PPP2PPP/RNBQKBNR w KQkq - 0 3",
+    "rnbqkb1r/pppp1ppp/5n2/4p3/2P5/2N5/PP1PPPPP/R1BQKBNR w KQkq - 2 3",
+];
+
+/// How long an engine can take to answer anything but `go`
+const READY_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long past its time limit an engine can take to answer `go` before it loses
+const MOVE_TIMEOUT_MARGIN: u64 = 1000;
+/// How long an engine has to quit before it's killed
+const QUIT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// An engine score, in centipawns from the side to move's point of view. Mates count as huge.
+const MATE_SCORE: i32 = 100_000;
+
+/// How the engines' time is limited
+#[derive(Clone, Copy, Debug)]
+pub enum TimeControl {
+    MoveTime(u64),
+    /// Base time and increment per move, in milliseconds
+    Clock(u64, u64),
+}
+
+/// When to end games early, based on the scores engines report
+#[derive(Clone, Copy, Debug)]
+pub struct Adjudication {
+    /// A side resigns after reporting a score at or below minus this
+    pub resign_score: i32,
+    /// for this many moves in a row
+    pub resign_moves: usize,
+    /// A game is drawn when both sides report a score within this
+    pub draw_score: i32,
+    /// for this many moves each
+    pub draw_moves: usize,
+    /// but not before this move number
+    pub draw_start: usize,
+}
+
+pub struct MatchConfig {
+    /// Command line of each engine, or `None` to use this binary
+    pub engines: [Option<String>; 2],
+    /// UCI options for each engine, as `name=value`
+    pub options: [Vec<String>; 2],
+    pub games: usize,
+    pub openings: Option<String>,
+    pub time_control: TimeControl,
+    pub adjudication: Adjudication,
+    pub pgn: Option<String>,
+    /// Elo of the null and alternative hypotheses, and the error rates of the SPRT
+    pub elo0: f64,
+    pub elo1: f64,
+    pub alpha: f64,
+    pub beta: f64,
+}
+
+/// An engine running in a child process, talked to over UCI
+struct UciEngine {
+    name: String,
+    /// How the engine was started, to restart it after it hangs
+    command: Option<String>,
+    options: Vec<String>,
+    process: Child,
+    stdin: ChildStdin,
+    /// Lines of output, read on another thread so reading them can time out
+    lines: Receiver<String>,
+}
+
+impl UciEngine {
+    fn start(command: Option<&str>, options: &[String]) -> io::Result<Self> {
+        let mut cmd = match command {
+            Some(command) => {
+                let mut parts = command.split_whitespace();
+                let mut cmd = Command::new(parts.next().unwrap_or_default());
+                cmd.args(parts);
+                cmd
+            }
+            None => {
+                let mut cmd = Command::new(env::current_exe()?);
+                cmd.arg("uci");
+                cmd
+            }
+        };
+        let mut process = cmd
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let stdin = process.stdin.take().unwrap();
+        let stdout = BufReader::new(process.stdout.take().unwrap());
+        let (sender, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in stdout.lines().map_while(Result::ok) {
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        let mut engine = Self {
+            name: "Unknown".to_string(),
+            command: command.map(str::to_string),
+            options: options.to_vec(),
+            process,
+            stdin,
+            lines,
+        };
+
+        engine.send("uci")?;
+        let mut name = None;
+        engine.read_until(READY_TIMEOUT, |line| {
+            if let Some(n) = line.strip_prefix("id name ") {
+                name = Some(n.to_string());
+            }
+            line == "uciok"
+        })?;
+        if let Some(name) = name {
+            engine.name = name;
+        }
+        for option in options {
+            let (name, value) = option.split_once('=').unwrap_or((option, ""));
+            engine.send(&format!(
+                "setoption name {} value {}",
+                name.trim(),
+                value.trim()
+            ))?;
+        }
+        engine.send("isready")?;
+        engine.read_until(READY_TIMEOUT, |line| line == "readyok")?;
+        Ok(engine)
+    }
+
+    /// Kill the engine and start it again, for when it stops responding
+    fn restart(&mut self) -> io::Result<()> {
+        let _ = self.process.kill();
+        *self = Self::start(self.command.as_deref(), &self.options)?;
+        Ok(())
+    }
+
+    fn send(&mut self, command: &str) -> io::Result<()> {
+        writeln!(self.stdin, "{}", command)?;
+        self.stdin.flush()
+    }
+
+    /// Read lines until `done` returns true for one, or fail with `TimedOut` after `timeout`
+    fn read_until(
+        &mut self,
+        timeout: Duration,
+        mut done: impl FnMut(&str) -> bool,
+    ) -> io::Result<()> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let line = self
+                .lines
+                .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                .map_err(|e| match e {
+                    RecvTimeoutError::Timeout => {
+                        io::Error::new(io::ErrorKind::TimedOut, "Engine stopped responding")
+                    }
+                    RecvTimeoutError::Disconnected => {
+                        io::Error::new(io::ErrorKind::UnexpectedEof, "Engine exited unexpectedly")
+                    }
+                })?;
+            if done(line.trim()) {
+                return Ok(());
+            }
+        }
+    }
+
+    fn new_game(&mut self) -> io::Result<()> {
+        self.send("ucinewgame")?;
+        self.send("isready")?;
+        self.read_until(READY_TIMEOUT, |line| line == "readyok")
+    }
+
+    /// Ask for a move in the position after `moves` from `fen`, giving up after `timeout`.
+    /// Returns the move and the last score reported for it.
+    fn go(
+        &mut self,
+        fen: &str,
+        moves: &[ChessMove],
+        go: &str,
+        timeout: Duration,
+    ) -> io::Result<(Option<ChessMove>, Option<i32>)> {
+        let mut position = format!("position fen {}", fen);
+        if !moves.is_empty() {
+            let moves: Vec<String> = moves.iter().map(|m| m.to_string()).collect();
+            position += &format!(" moves {}", moves.join(" "));
+        }
+        self.send(&position)?;
+        self.send(go)?;
+
+        let mut score = None;
+        let mut best_move = None;
+        self.read_until(timeout, |line| {
+            let mut tokens = line.split_whitespace();
+            match tokens.next() {
+                Some("info") => {
+                    // Bounds aren't exact scores
+                    if line.contains("lowerbound") || line.contains("upperbound") {
+                        return false;
+                    }
+                    while let Some(token) = tokens.next() {
+                        if token == "score" {
+                            score = match (tokens.next(), tokens.next().map(i32::from_str)) {
+                                (Some("cp"), Some(Ok(cp))) => Some(cp),
+                                (Some("mate"), Some(Ok(n))) => Some(MATE_SCORE * n.signum()),
+                                _ => score,
+                            };
+                        }
+                    }
+                    false
+                }
+                Some("bestmove") => {
+                    best_move = tokens.next().and_then(|m| ChessMove::from_str(m).ok());
+                    true
+                }
+                _ => false,
+            }
+        })?;
+        Ok((best_move, score))
+    }
+}
+
+impl Drop for UciEngine {
+    fn drop(&mut self) {
+        let _ = self.send("quit");
+        // One that hangs shouldn't hold up the match
+        let deadline = Instant::now() + QUIT_TIMEOUT;
+        while let Ok(None) = self.process.try_wait() {
+            if Instant::now() >= deadline {
+                let _ = self.process.kill();
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        let _ = self.process.wait();
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Outcome {
+    Win(Color),
+    Draw,
+}
+
+impl Outcome {
+    fn pgn(self) -> &'static str {
+        match self {
+            Outcome::Win(Color::White) => "1-0",
+            Outcome::Win(Color::Black) => "0-1",
+            Outcome::Draw => "1/2-1/2",
+        }
+    }
+}
+
+/// Neither side can possibly checkmate
+fn insufficient_material(board: &Board) -> bool {
+    let heavy = board.pieces(Piece::Pawn) | board.pieces(Piece::Rook) | board.pieces(Piece::Queen);
+    let minors = board.pieces(Piece::Knight) | board.pieces(Piece::Bishop);
+    heavy.popcnt() == 0 && minors.popcnt() <= 1
+}
+
+/// Play one game from `fen`. `engines[0]` plays white.
+fn play_game(
+    engines: &mut [&mut UciEngine; 2],
+    fen: &str,
+    config: &MatchConfig,
+) -> io::Result<(Game, Outcome, String)> {
+    for engine in engines.iter_mut() {
+        engine.new_game()?;
+    }
+    let mut board = Board::from_str(fen).expect("Invalid opening");
+    let mut game = Game::new(Some(fen.to_string()));
+    let mut clocks = match config.time_control {
+        TimeControl::Clock(base, _) => [base as i64; 2],
+        TimeControl::MoveTime(_) => [0; 2],
+    };
+    let mut positions: HashMap<u64, usize> = HashMap::new();
+    positions.insert(board.get_hash(), 1);
+    let fen_field = |i| fen.split_whitespace().nth(i).and_then(|n| n.parse().ok());
+    let mut halfmove_clock = fen_field(4).unwrap_or(0);
+    let mut move_number = fen_field(5).unwrap_or(1);
+    // Consecutive scores of each side that allow adjudication
+    let mut losing = [0; 2];
+    let mut drawish = [0; 2];
+    let adjudication = config.adjudication;
+
+    let (outcome, reason) = loop {
+        match board.status() {
+            BoardStatus::Checkmate => break (Outcome::Win(!board.side_to_move()), "checkmate"),
+            BoardStatus::Stalemate => break (Outcome::Draw, "stalemate"),
+            BoardStatus::Ongoing => {}
+        }
+        if halfmove_clock >= 100 {
+            break (Outcome::Draw, "fifty-move rule");
+        }
+        if positions[&board.get_hash()] >= 3 {
+            break (Outcome::Draw, "threefold repetition");
+        }
+        if insufficient_material(&board) {
+            break (Outcome::Draw, "insufficient material");
+        }
+
+        let side = board.side_to_move().to_index();
+        let (go, limit) = match config.time_control {
+            TimeControl::MoveTime(movetime) => (format!("go movetime {}", movetime), movetime),
+            TimeControl::Clock(_, inc) => (
+                format!(
+                    "go wtime {} btime {} winc {} binc {}",
+                    clocks[0], clocks[1], inc, inc
+                ),
+                clocks[side].max(0) as u64,
+            ),
+        };
+        let timeout = Duration::from_millis(limit + MOVE_TIMEOUT_MARGIN);
+        let start = Instant::now();
+        let (m, score) = match engines[side].go(fen, &game.moves, &go, timeout) {
+            Ok(result) => result,
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+                engines[side].restart()?;
+                break (Outcome::Win(!board.side_to_move()), "timed out");
+            }
+            Err(e) => return Err(e),
+        };
+        if let TimeControl::Clock(_, inc) = config.time_control {
+            clocks[side] -= start.elapsed().as_millis() as i64;
+            if clocks[side] < 0 {
+                break (Outcome::Win(!board.side_to_move()), "loss on time");
+            }
+            clocks[side] += inc as i64;
+        }
+        let m = match m {
+            Some(m) if board.legal(m) => m,
+            _ => break (Outcome::Win(!board.side_to_move()), "illegal move"),
+        };
+
+        if let Some(score) = score {
+            losing[side] = if score <= -adjudication.resign_score {
+                losing[side] + 1
+            } else {
+                0
+            };
+            drawish[side] = if score.abs() <= adjudication.draw_score {
+                drawish[side] + 1
+            } else {
+                0
+            };
+        }
+
+        let irreversible = board.piece_on(m.get_dest()).is_some()
+            || board.piece_on(m.get_source()) == Some(Piece::Pawn);
+        halfmove_clock = if irreversible { 0 } else { halfmove_clock + 1 };
+        if board.side_to_move() == Color::Black {
+            move_number += 1;
+        }
+        board = board.make_move_new(m);
+        game.moves.push(m);
+        *positions.entry(board.get_hash()).or_insert(0) += 1;
+
+        if losing[side] >= adjudication.resign_moves {
+            break (
+                Outcome::Win(board.side_to_move()),
+                "adjudicated resignation",
+            );
+        }
+        if move_number >= adjudication.draw_start
+            && drawish.iter().all(|&n| n >= adjudication.draw_moves)
+        {
+            break (Outcome::Draw, "adjudicated draw");
+        }
+    };
+
+    game.set_tag("Result", outcome.pgn());
+    game.set_tag("Termination", reason);
+    Ok((game, outcome, reason.to_string()))
+}
+
+fn load_openings(path: &Option<String>) -> Result<Vec<String>, String> {
+    let path = match path {
+        Some(path) => path,
+        None => return Ok(DEFAULT_OPENINGS.iter().map(|s| s.to_string()).collect()),
+    };
+    let contents =
+        fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    // The position at the end of each game is the opening
+    // Bad games or lines are skipped, so one mistake doesn't make a whole book unusable
+    let openings: Vec<String> = if path.ends_with(".pgn") {
+        parse_pgn(&contents)
+            .into_iter()
+            .filter_map(|game| match game {
+                Ok(game) => Some(game.history(game.moves.len()).0.to_string()),
+                Err(e) => {
+                    println!("Skipping opening: {}", e);
+                    None
+                }
+            })
+            .collect()
+    } else {
+        contents
+            .lines()
+            .enumerate()
+            .map(|(i, line)| (i, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|(i, line)| {
+                // Both FEN and EPD lines work, the opcodes are ignored
+                match parse_epd(line) {
+                    Ok(entry) => Some(entry.board.to_string()),
+                    Err(e) => {
+                        println!("Skipping opening on line {}: {}", i + 1, e);
+                        None
+                    }
+                }
+            })
+            .collect()
+    };
+    if openings.is_empty() {
+        return Err(format!("No openings in {}", path));
+    }
+    Ok(openings)
+}
+
+/// Play games between two engines, and print the score, Elo difference and SPRT result
+pub fn run_match(config: MatchConfig) -> Result<(), String> {
+    let openings = load_openings(&config.openings)?;
+    let start = |i: usize| {
+        UciEngine::start(config.engines[i].as_deref(), &config.options[i])
+            .map_err(|e| format!("Failed to start engine {}: {}", i + 1, e))
+    };
+    let mut first = start(0)?;
+    let mut second = start(1)?;
+    // Tell them apart when both are this binary
+    let names = [
+        format!("{} (A)", first.name),
+        format!("{} (B)", second.name),
+    ];
+
+    let mut pgn_file = match &config.pgn {
+        Some(path) => Some(
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|e| format!("Failed to open {}: {}", path, e))?,
+        ),
+        None => None,
+    };
+
+    let bounds = sprt_bounds(config.alpha, config.beta);
+    let mut score = MatchScore::default();
+    for round in 0..config.games {
+        // Every opening is played twice, with colors reversed
+        let fen = &openings[(round / 2) % openings.len()];
+        let first_is_white = round % 2 == 0;
+        let mut engines = if first_is_white {
+            [&mut first, &mut second]
+        } else {
+            [&mut second, &mut first]
+        };
+        let (mut game, outcome, reason) =
+            play_game(&mut engines, fen, &config).map_err(|e| format!("Engine error: {}", e))?;
+
+        let first_color = if first_is_white {
+            Color::White
+        } else {
+            Color::Black
+        };
+        match outcome {
+            Outcome::Win(color) if color == first_color => score.wins += 1,
+            Outcome::Win(_) => score.losses += 1,
+            Outcome::Draw => score.draws += 1,
+        }
+
+        let (white, black) = if first_is_white {
+            (&names[0], &names[1])
+        } else {
+            (&names[1], &names[0])
+        };
+        game.set_tag("Event", "Crabfish match");
+        game.set_tag("Site", "?");
+        game.set_tag("Round", &(round + 1).to_string());
+        game.set_tag("White", white);
+        game.set_tag("Black", black);
+        if let Some(file) = pgn_file.as_mut() {
+            file.write_all(game.to_pgn().as_bytes())
+                .map_err(|e| format!("Failed to write PGN: {}", e))?;
+        }
+
+        let llr = score.llr(config.elo0, config.elo1);
+        println!(
+            "Game {}: {} vs {}: {} ({})",
+            round + 1,
+            white,
+            black,
+            outcome.pgn(),
+            reason
+        );
+        println!(
+            "Score of {} vs {}: {} - {} - {} [{:.3}] {}, LLR {:.2} ({:.2}, {:.2})",
+            names[0],
+            names[1],
+            score.wins,
+            score.losses,
+            score.draws,
+            (score.wins as f64 + score.draws as f64 / 2.0) / score.games() as f64,
+            score.games(),
+            llr,
+            bounds.0,
+            bounds.1
+        );
+        if sprt(llr, bounds) != SprtResult::Continue {
+            break;
+        }
+    }
+
+    let (elo, error) = score.elo();
+    println!();
+    println!("Elo difference: {:.1} +/- {:.1}", elo, error);
+    println!(
+        "SPRT ({} to {} Elo): {}",
+        config.elo0,
+        config.elo1,
+        match sprt(score.llr(config.elo0, config.elo1), bounds) {
+            SprtResult::AcceptH1 => "H1 accepted",
+            SprtResult::AcceptH0 => "H0 accepted",
+            SprtResult::Continue => "inconclusive",
+        }
+    );
+    Ok(())
+}
@@ -1,34 +1,133 @@
-use chess::{Board, ChessMove};
+use chess::{Board, ChessMove, Color, Piece};
+use smallvec::SmallVec;
 use std::cmp::Reverse;
 
-use crate::{eval::get_score, score::ScoreTy, search::KILLER_MOVES};
+use crate::{
+    eval::get_score, helpers::captured_piece, score::ScoreTy, search::KILLER_MOVES, see::see,
+};
 
 #[inline(always)]
 fn mvv_lva(board: &Board, m: &ChessMove) -> Reverse<Option<(ScoreTy, Reverse<ScoreTy>)>> {
-    return Reverse(board.piece_on(m.get_dest()).map(|p| {
+    Reverse(board.piece_on(m.get_dest()).map(|p| {
         (
             get_score(p),
             Reverse(get_score(board.piece_on(m.get_source()).unwrap())),
         )
-    }));
+    }))
+}
+
+/// Largest magnitude a history score can reach
+const MAX_HISTORY: i32 = 16384;
+
+/// Butterfly history: how well each quiet move, by side and from and to square, has done at
+/// causing beta cutoffs
+pub struct MoveHistory(Box<[[[i32; 64]; 64]; 2]>);
+
+impl MoveHistory {
+    pub fn new() -> Self {
+        Self(Box::new([[[0; 64]; 64]; 2]))
+    }
+
+    #[inline(always)]
+    pub fn get(&self, color: Color, m: ChessMove) -> i32 {
+        self.0[color.to_index()][m.get_source().to_index()][m.get_dest().to_index()]
+    }
+
+    /// Move the score towards `bonus`. Scores stay within `MAX_HISTORY`, and the larger they get
+    /// the less they grow, so old results fade out.
+    #[inline(always)]
+    pub fn update(&mut self, color: Color, m: ChessMove, bonus: i32) {
+        let entry =
+            &mut self.0[color.to_index()][m.get_source().to_index()][m.get_dest().to_index()];
+        *entry += bonus - *entry * bonus.abs() / MAX_HISTORY;
+    }
+}
+
+/// The last quiet move that refuted each move, by the refuted move's from and to square
+pub struct CounterMoves(Box<[[Option<ChessMove>; 64]; 64]>);
+
+impl CounterMoves {
+    pub fn new() -> Self {
+        Self(Box::new([[None; 64]; 64]))
+    }
+
+    #[inline(always)]
+    pub fn get(&self, prev: ChessMove) -> Option<ChessMove> {
+        self.0[prev.get_source().to_index()][prev.get_dest().to_index()]
+    }
+
+    #[inline(always)]
+    pub fn set(&mut self, prev: ChessMove, m: ChessMove) {
+        self.0[prev.get_source().to_index()][prev.get_dest().to_index()] = Some(m);
+    }
+}
+
+/// Ordering of a move, after the transposition table move: winning and equal captures and queen
+/// promotions by MVV-LVA, then killers, then the countermove, then other quiet moves by history,
+/// then losing captures by how much they lose, then underpromotions
+#[inline(always)]
+fn move_class(
+    board: &Board,
+    m: &ChessMove,
+    killer_moves: &[Option<ChessMove>; KILLER_MOVES],
+    history: &MoveHistory,
+    countermove: Option<ChessMove>,
+) -> (u8, i32) {
+    let victim = captured_piece(board, *m);
+    let promotion = m.get_promotion();
+    if victim.is_some() || promotion == Some(Piece::Queen) {
+        let attacker = board.piece_on(m.get_source()).unwrap();
+        let gain = victim.map_or(0, get_score)
+            + promotion.map_or(0, |p| get_score(p) - get_score(Piece::Pawn));
+        // Taking a piece worth at least as much can't lose material
+        let exchange = if victim.is_some_and(|v| get_score(v) >= get_score(attacker)) {
+            0
+        } else {
+            see(board, *m)
+        };
+        if exchange >= 0 {
+            (0, -(gain as i32 * 8) + attacker.to_index() as i32)
+        } else {
+            (3, -exchange as i32)
+        }
+    } else if promotion.is_some() {
+        // Underpromotions are almost never best
+        (4, 0)
+    } else {
+        match killer_moves.iter().position(|v| v.as_ref() == Some(m)) {
+            Some(pos) => (1, pos as i32),
+            None if countermove == Some(*m) => (1, KILLER_MOVES as i32),
+            None => (2, -history.get(board.side_to_move(), *m)),
+        }
+    }
 }
 
 #[inline(always)]
 pub fn sort_moves(
     board: &Board,
     moves: &mut [ChessMove],
+    tt_move: Option<ChessMove>,
     killer_moves: &[Option<ChessMove>; KILLER_MOVES],
+    history: &MoveHistory,
+    countermove: Option<ChessMove>,
 ) {
-    moves.sort_by_key(|m| {
-        let mvv_lva = mvv_lva(board, m);
-        (
-            mvv_lva,
-            killer_moves
-                .iter()
-                .position(|v| v.as_ref() == Some(m))
-                .unwrap_or(KILLER_MOVES + 1),
-        )
-    });
+    // Score each move once, on the stack, with its index to keep equal moves in generation order
+    let mut keyed: SmallVec<[_; 64]> = moves
+        .iter()
+        .enumerate()
+        .map(|(i, m)| {
+            (
+                Some(*m) != tt_move,
+                move_class(board, m, killer_moves, history, countermove),
+                i as u8,
+                *m,
+            )
+        })
+        .collect();
+    keyed.sort_unstable_by_key(|&(not_tt, class, i, _)| (not_tt, class, i));
+    for (m, &(.., sorted)) in moves.iter_mut().zip(keyed.iter()) {
+        *m = sorted;
+    }
 }
 
 #[inline(always)]
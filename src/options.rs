@@ -0,0 +1,177 @@
+use std::fmt;
+
+use crate::{
+    search::{Engine, ASPIRATION_WINDOW, FUTILITY_MARGIN, NULL_MOVE_REDUCTION},
+    time_manager::DEFAULT_MOVE_OVERHEAD,
+    transposition::DEFAULT_HASH_MB,
+};
+
+pub enum OptionType {
+    Spin { default: i64, min: i64, max: i64 },
+    Check { default: bool },
+    Button,
+}
+
+pub struct UciOption {
+    pub name: &'static str,
+    pub ty: OptionType,
+}
+
+impl fmt::Display for UciOption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "option name {} type ", self.name)?;
+        match self.ty {
+            OptionType::Spin { default, min, max } => {
+                write!(f, "spin default {} min {} max {}", default, min, max)
+            }
+            OptionType::Check { default } => write!(f, "check default {}", default),
+            OptionType::Button => write!(f, "button"),
+        }
+    }
+}
+
+pub const OPTIONS: &[UciOption] = &[
+    UciOption {
+        name: "Hash",
+        ty: OptionType::Spin {
+            default: DEFAULT_HASH_MB as i64,
+            min: 1,
+            max: 65536,
+        },
+    },
+    UciOption {
+        name: "Clear Hash",
+        ty: OptionType::Button,
+    },
+    UciOption {
+        name: "Threads",
+        ty: OptionType::Spin {
+            default: 1,
+            min: 1,
+            max: 512,
+        },
+    },
+    UciOption {
+        name: "MultiPV",
+        ty: OptionType::Spin {
+            default: 1,
+            min: 1,
+            max: 256,
+        },
+    },
+    UciOption {
+        name: "Move Overhead",
+        ty: OptionType::Spin {
+            default: DEFAULT_MOVE_OVERHEAD as i64,
+            min: 0,
+            max: 5000,
+        },
+    },
+    UciOption {
+        name: "Ponder",
+        ty: OptionType::Check { default: false },
+    },
+    UciOption {
+        name: "Contempt",
+        ty: OptionType::Spin {
+            default: 0,
+            min: -100,
+            max: 100,
+        },
+    },
+    // Search parameters, so two configurations of one binary can play a match
+    UciOption {
+        name: "Null Move Reduction",
+        ty: OptionType::Spin {
+            default: NULL_MOVE_REDUCTION as i64,
+            min: 1,
+            max: 4,
+        },
+    },
+    UciOption {
+        name: "Aspiration Window",
+        ty: OptionType::Spin {
+            default: ASPIRATION_WINDOW as i64,
+            min: 1,
+            max: 500,
+        },
+    },
+    UciOption {
+        name: "Futility Margin",
+        ty: OptionType::Spin {
+            default: FUTILITY_MARGIN as i64,
+            min: 0,
+            max: 1000,
+        },
+    },
+];
+
+pub fn print_options() {
+    for option in OPTIONS {
+        println!("{}", option);
+    }
+}
+
+/// Split the arguments of `setoption name <name> [value <value>]`. Both may contain spaces.
+pub fn parse_setoption<'a>(mut items: impl Iterator<Item = &'a str>) -> Option<(String, String)> {
+    if items.next()? != "name" {
+        return None;
+    }
+    let mut name = Vec::new();
+    let mut value = Vec::new();
+    let mut in_value = false;
+    for item in items {
+        match item {
+            "value" if !in_value => in_value = true,
+            _ if in_value => value.push(item),
+            _ => name.push(item),
+        }
+    }
+    Some((name.join(" "), value.join(" ")))
+}
+
+/// Validate `value` for the option called `name` and apply it to `engine`
+pub fn set_option(engine: &mut Engine, name: &str, value: &str) -> Result<(), String> {
+    let option = OPTIONS
+        .iter()
+        .find(|o| o.name.eq_ignore_ascii_case(name))
+        .ok_or_else(|| format!("No such option: {}", name))?;
+
+    let value = match option.ty {
+        OptionType::Spin { min, max, .. } => {
+            let v: i64 = value
+                .parse()
+                .map_err(|_| format!("Invalid value for {}: {}", option.name, value))?;
+            if v < min || v > max {
+                return Err(format!(
+                    "Value for {} must be between {} and {}",
+                    option.name, min, max
+                ));
+            }
+            v
+        }
+        OptionType::Check { .. } => match value {
+            "true" => 1,
+            "false" => 0,
+            _ => return Err(format!("Invalid value for {}: {}", option.name, value)),
+        },
+        OptionType::Button => 0,
+    };
+
+    match option.name {
+        "Hash" => engine.resize_hash(value as usize),
+        "Clear Hash" => engine.new_game(),
+        "Threads" => engine.set_threads(value as usize),
+        "MultiPV" => engine.multipv = value as usize,
+        "Move Overhead" => engine.move_overhead = value as u64,
+        "Contempt" => engine.contempt = value as i16,
+        "Null Move Reduction" => engine.params.null_move_reduction = value as u8,
+        "Aspiration Window" => engine.params.aspiration_window = value as i16,
+        "Futility Margin" => engine.params.futility_margin = value as i16,
+        // Only tells us the GUI may send `go ponder`, nothing to change
+        "Ponder" => {}
+        _ => unreachable!(),
+    }
+
+    Ok(())
+}
@@ -0,0 +1,150 @@
+use chess::{
+    get_adjacent_files, get_file, get_pawn_attacks, get_rank, BitBoard, Board, Color, Piece, Rank,
+    Square,
+};
+
+use crate::eval::Tapered;
+
+const DOUBLED: Tapered = Tapered(-10, -25);
+const ISOLATED: Tapered = Tapered(-8, -15);
+const BACKWARD: Tapered = Tapered(-8, -12);
+/// Indexed by rank, relative to the pawn's side
+const CONNECTED: [Tapered; 8] = [
+    Tapered(0, 0),
+    Tapered(4, 2),
+    Tapered(7, 4),
+    Tapered(10, 6),
+    Tapered(18, 12),
+    Tapered(30, 25),
+    Tapered(50, 40),
+    Tapered(0, 0),
+];
+const PASSED: [Tapered; 8] = [
+    Tapered(0, 0),
+    Tapered(3, 10),
+    Tapered(6, 18),
+    Tapered(12, 32),
+    Tapered(25, 55),
+    Tapered(45, 95),
+    Tapered(70, 140),
+    Tapered(0, 0),
+];
+
+const PAWN_TABLE_SIZE: usize = 1 << 14;
+
+#[derive(Clone, Copy, Default)]
+struct PawnEntry {
+    key: u64,
+    score: Tapered,
+}
+
+/// Caches pawn structure scores, which only change when a pawn moves or is captured
+pub struct PawnTable {
+    entries: Vec<PawnEntry>,
+}
+
+impl PawnTable {
+    pub fn new() -> Self {
+        Self {
+            entries: vec![PawnEntry::default(); PAWN_TABLE_SIZE],
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries
+            .iter_mut()
+            .for_each(|e| *e = PawnEntry::default());
+    }
+}
+
+#[inline(always)]
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+/// All squares on ranks strictly in front of `rank`, from `color`'s point of view
+#[inline(always)]
+pub fn forward_ranks(color: Color, rank: Rank) -> BitBoard {
+    let rank = rank.to_index();
+    match color {
+        Color::White if rank < 7 => BitBoard(!0 << (8 * (rank + 1))),
+        Color::Black => BitBoard((1 << (8 * rank)) - 1),
+        _ => BitBoard(0),
+    }
+}
+
+#[inline(always)]
+pub fn relative_rank(color: Color, square: Square) -> usize {
+    match color {
+        Color::White => square.get_rank().to_index(),
+        Color::Black => 7 - square.get_rank().to_index(),
+    }
+}
+
+/// Whether the pawn of `color` on `square` has no enemy pawns in front of it, or beside its path
+#[inline(always)]
+pub fn is_passed(square: Square, color: Color, enemy_pawns: BitBoard) -> bool {
+    let span = get_file(square.get_file()) | get_adjacent_files(square.get_file());
+    enemy_pawns & span & forward_ranks(color, square.get_rank()) == BitBoard(0)
+}
+
+fn pawn_structure(own: BitBoard, enemy: BitBoard, color: Color) -> Tapered {
+    let mut score = Tapered::default();
+
+    for square in own {
+        let file = get_file(square.get_file());
+        let adjacent = get_adjacent_files(square.get_file());
+        let ahead = forward_ranks(color, square.get_rank());
+        let rank = relative_rank(color, square);
+
+        let doubled = own & file & ahead != BitBoard(0);
+        let isolated = own & adjacent == BitBoard(0);
+        let supported = get_pawn_attacks(square, !color, own) != BitBoard(0);
+        let phalanx = own & adjacent & get_rank(square.get_rank()) != BitBoard(0);
+
+        if doubled {
+            score += DOUBLED;
+        }
+        if isolated {
+            score += ISOLATED;
+        }
+        if supported || phalanx {
+            score += CONNECTED[rank];
+        } else if !isolated && own & adjacent & !ahead == BitBoard(0) {
+            // All neighbours have advanced past this pawn, so it can't be defended by them, and an
+            // enemy pawn keeps it from catching up
+            let stop_attacked = square
+                .forward(color)
+                .is_some_and(|stop| get_pawn_attacks(stop, color, enemy) != BitBoard(0));
+            if stop_attacked {
+                score += BACKWARD;
+            }
+        }
+        // Only the front pawn of a doubled pair counts as passed
+        if !doubled && is_passed(square, color, enemy) {
+            score += PASSED[rank];
+        }
+    }
+
+    score
+}
+
+/// Pawn structure score from white's point of view
+pub fn evaluate_pawns(board: &Board, table: &mut PawnTable) -> Tapered {
+    let pawns = board.pieces(Piece::Pawn);
+    let white = pawns & board.color_combined(Color::White);
+    let black = pawns & board.color_combined(Color::Black);
+
+    let key = mix(white.0 ^ mix(black.0));
+    let entry = &mut table.entries[key as usize % PAWN_TABLE_SIZE];
+    if entry.key == key {
+        return entry.score;
+    }
+
+    let score =
+        pawn_structure(white, black, Color::White) - pawn_structure(black, white, Color::Black);
+    *entry = PawnEntry { key, score };
+    score
+}
@@ -0,0 +1,120 @@
+use std::time::Instant;
+
+use chess::{Board, ChessMove, MoveGen};
+
+/// Number of leaf nodes `depth` plies below `board`, generated the same way the search does
+pub fn perft(board: Board, depth: u8) -> u64 {
+    let moves = MoveGen::new_legal(&board);
+    match depth {
+        0 => 1,
+        1 => moves.len() as u64,
+        _ => moves
+            .map(|m| perft(board.make_move_new(m), depth - 1))
+            .sum(),
+    }
+}
+
+/// Perft of every root move separately
+pub fn divide(board: Board, depth: u8) -> Vec<(ChessMove, u64)> {
+    MoveGen::new_legal(&board)
+        .map(|m| (m, perft(board.make_move_new(m), depth.saturating_sub(1))))
+        .collect()
+}
+
+/// Print the perft of `board`, and how fast it was calculated
+pub fn run_perft(board: Board, depth: u8, show_divide: bool) {
+    let start = Instant::now();
+    let nodes = if show_divide {
+        let moves = divide(board, depth);
+        for (m, count) in &moves {
+            println!("{}: {}", m, count);
+        }
+        println!();
+        moves.iter().map(|(_, count)| count).sum()
+    } else {
+        perft(board, depth)
+    };
+    let elapsed = start.elapsed();
+
+    println!("Nodes searched: {}", nodes);
+    println!("Time: {}ms", elapsed.as_millis());
+    println!(
+        "Nodes/second: {}",
+        (nodes as f64 / elapsed.as_secs_f64().max(1e-9)) as u64
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    fn check(fen: &str, expected: &[u64]) {
+        let board = Board::from_str(fen).unwrap();
+        for (depth, &nodes) in expected.iter().enumerate() {
+            assert_eq!(
+                perft(board, depth as u8 + 1),
+                nodes,
+                "{} at depth {}",
+                fen,
+                depth + 1
+            );
+        }
+    }
+
+    #[test]
+    fn startpos() {
+        check(
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            &[20, 400, 8902, 197281],
+        );
+    }
+
+    #[test]
+    fn kiwipete() {
+        check(
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            &[48, 2039, 97862],
+        );
+    }
+
+    #[test]
+    fn en_passant_and_pins() {
+        check(
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            &[14, 191, 2812, 43238],
+        );
+    }
+
+    #[test]
+    fn promotions_and_castling() {
+        check(
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            &[6, 264, 9467],
+        );
+    }
+
+    #[test]
+    fn underpromotion_checks() {
+        check(
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+            &[44, 1486, 62379],
+        );
+    }
+
+    #[test]
+    fn promotions_and_castling_mirrored() {
+        check(
+            "r2q1rk1/pP1p2pp/Q4n2/bbp1p3/Np6/1B3NBn/pPPP1PPP/R3K2R b KQ - 0 1",
+            &[6, 264, 9467],
+        );
+    }
+
+    #[test]
+    fn divide_sums_to_perft() {
+        let board = Board::default();
+        let total: u64 = divide(board, 3).iter().map(|(_, n)| n).sum();
+        assert_eq!(total, perft(board, 3));
+    }
+}
@@ -0,0 +1,553 @@
+use std::{iter::Peekable, mem, str::Chars};
+
+use chess::{Board, ChessMove, Color};
+
+use crate::{
+    helpers::halfmove_clock,
+    san::{parse_san, to_san},
+    search::HistoryEntry,
+};
+
+/// Tags that every PGN game has, in the order they're written
+const SEVEN_TAG_ROSTER: [&str; 7] = ["Event", "Site", "Date", "Round", "White", "Black", "Result"];
+const LINE_WIDTH: usize = 80;
+const RESULTS: [&str; 4] = ["1-0", "0-1", "1/2-1/2", "*"];
+
+/// A game record: where it started, the moves played and how it ended
+#[derive(Clone, Debug)]
+pub struct Game {
+    pub tags: Vec<(String, String)>,
+    /// The FEN of the starting position, or `None` for the standard one
+    pub fen: Option<String>,
+    pub moves: Vec<ChessMove>,
+    /// Comments, with the number of moves played before each one
+    pub comments: Vec<(usize, String)>,
+    /// Numeric annotation glyphs, like `$1` for a good move, after the move they annotate
+    pub nags: Vec<(usize, u8)>,
+    /// Variations as PGN text, after the move they're an alternative to
+    pub variations: Vec<(usize, String)>,
+}
+
+impl Game {
+    pub fn new(fen: Option<String>) -> Self {
+        Self {
+            tags: Vec::new(),
+            fen,
+            moves: Vec::new(),
+            comments: Vec::new(),
+            nags: Vec::new(),
+            variations: Vec::new(),
+        }
+    }
+
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn set_tag(&mut self, name: &str, value: &str) {
+        match self.tags.iter_mut().find(|(n, _)| n == name) {
+            Some(tag) => tag.1 = value.to_string(),
+            None => self.tags.push((name.to_string(), value.to_string())),
+        }
+    }
+
+    pub fn start_board(&self) -> Board {
+        self.fen
+            .as_ref()
+            .map_or_else(Board::default, |fen| fen.parse().expect("Invalid FEN"))
+    }
+
+    /// The position after the first `ply` moves, and the positions leading up to it
+    pub fn history(&self, ply: usize) -> (Board, Vec<HistoryEntry>) {
+        let mut board = self.start_board();
+        let clock = self.fen.as_deref().map_or(0, halfmove_clock);
+        let mut history = vec![HistoryEntry::new(&board, clock)];
+        for &m in &self.moves[..ply.min(self.moves.len())] {
+            let new_board = board.make_move_new(m);
+            history.push(history.last().unwrap().after(&board, m, &new_board));
+            board = new_board;
+        }
+        (board, history)
+    }
+
+    /// Write the game as PGN, with moves in SAN
+    pub fn to_pgn(&self) -> String {
+        let mut pgn = String::new();
+        let result = self.tag("Result").unwrap_or("*");
+        for name in SEVEN_TAG_ROSTER {
+            let value = match name {
+                "Result" => result,
+                "Date" => self.tag(name).unwrap_or("????.??.??"),
+                _ => self.tag(name).unwrap_or("?"),
+            };
+            pgn += &format!("[{} \"{}\"]\n", name, escape(value));
+        }
+        if let Some(fen) = &self.fen {
+            pgn += &format!("[SetUp \"1\"]\n[FEN \"{}\"]\n", fen);
+        }
+        for (name, value) in &self.tags {
+            if !SEVEN_TAG_ROSTER.contains(&name.as_str()) && name != "SetUp" && name != "FEN" {
+                pgn += &format!("[{} \"{}\"]\n", name, escape(value));
+            }
+        }
+        pgn.push('\n');
+
+        let mut board = self.start_board();
+        let mut move_number = self
+            .fen
+            .as_ref()
+            .and_then(|fen| fen.split_whitespace().nth(5)?.parse().ok())
+            .unwrap_or(1);
+        let mut tokens = Vec::new();
+        // Black's moves only need a number at the start, or after a comment or variation
+        let mut numbered = false;
+        // Write what comes after `ply` moves, and return whether it needs the next move numbered
+        let annotate = |tokens: &mut Vec<String>, ply: usize| {
+            // Anything past the last move goes at the end
+            let at = |p: usize| p == ply || (ply == self.moves.len() && p > ply);
+            for (_, nag) in self.nags.iter().filter(|(p, _)| at(*p)) {
+                tokens.push(format!("${}", nag));
+            }
+            let before = tokens.len();
+            for (_, comment) in self.comments.iter().filter(|(p, _)| at(*p)) {
+                tokens.push(format!("{{{}}}", comment));
+            }
+            for (_, variation) in self.variations.iter().filter(|(p, _)| at(*p)) {
+                tokens.push(format!("({})", variation));
+            }
+            tokens.len() > before
+        };
+        annotate(&mut tokens, 0);
+        for (i, &m) in self.moves.iter().enumerate() {
+            match board.side_to_move() {
+                Color::White => tokens.push(format!("{}.", move_number)),
+                Color::Black if !numbered => tokens.push(format!("{}...", move_number)),
+                Color::Black => {}
+            }
+            numbered = true;
+            tokens.push(to_san(&board, m));
+            if board.side_to_move() == Color::Black {
+                move_number += 1;
+            }
+            board = board.make_move_new(m);
+            if annotate(&mut tokens, i + 1) {
+                numbered = false;
+            }
+        }
+        tokens.push(result.to_string());
+
+        let mut line = String::new();
+        for token in tokens {
+            if !line.is_empty() && line.len() + 1 + token.len() > LINE_WIDTH {
+                pgn += &line;
+                pgn.push('\n');
+                line.clear();
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line += &token;
+        }
+        pgn += &line;
+        pgn += "\n\n";
+        pgn
+    }
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Consume characters up to and including `end`, and return the ones before it
+fn read_until(chars: &mut Peekable<Chars>, end: char) -> String {
+    chars.by_ref().take_while(|&c| c != end).collect()
+}
+
+/// Parse the inside of a tag pair, like `Event "Casual game"`
+fn parse_tag(tag: &str) -> Result<(String, String), String> {
+    let (name, value) = tag
+        .trim()
+        .split_once(char::is_whitespace)
+        .ok_or_else(|| format!("Invalid tag: [{}]", tag))?;
+    let value = value.trim();
+    let value = value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .ok_or_else(|| format!("Invalid tag: [{}]", tag))?;
+    Ok((
+        name.to_string(),
+        value.replace("\\\"", "\"").replace("\\\\", "\\"),
+    ))
+}
+
+/// A game whose movetext is being read
+struct PartialGame {
+    game: Game,
+    board: Board,
+}
+
+impl PartialGame {
+    fn new(tags: Vec<(String, String)>) -> Result<Self, String> {
+        let fen = tags
+            .iter()
+            .find(|(name, _)| name == "FEN")
+            .map(|(_, fen)| fen.clone());
+        let mut game = Game::new(fen);
+        game.tags = tags
+            .into_iter()
+            .filter(|(name, _)| name != "FEN" && name != "SetUp")
+            .collect();
+        let board = match &game.fen {
+            Some(fen) => fen
+                .parse()
+                .map_err(|_| format!("Invalid FEN tag: {}", fen))?,
+            None => Board::default(),
+        };
+        Ok(Self { game, board })
+    }
+}
+
+/// The games read so far, and the one being read
+#[derive(Default)]
+struct PgnReader {
+    games: Vec<Result<Game, String>>,
+    tags: Vec<(String, String)>,
+    current: Option<PartialGame>,
+    /// Whether the current game's tags are done
+    in_movetext: bool,
+    /// Why the current game can't be read. The rest of it is skipped.
+    error: Option<String>,
+    /// Comments before the next game's movetext, which lead it
+    leading_comments: Vec<String>,
+}
+
+impl PgnReader {
+    fn read_tag(&mut self, tag: &str) {
+        // Tags after movetext start the next game, even if the last one had no result
+        if self.in_movetext {
+            self.finish();
+        }
+        match parse_tag(tag) {
+            Ok(tag) => self.tags.push(tag),
+            Err(e) => {
+                self.error.get_or_insert(e);
+            }
+        }
+    }
+
+    fn read_comment(&mut self, comment: &str) {
+        // A comment after the tags starts the movetext, but without tags it could be before them
+        if !self.tags.is_empty() {
+            self.start_movetext();
+        }
+        if !self.in_movetext {
+            self.leading_comments.push(comment.trim().to_string());
+        } else if let (None, Some(partial)) = (&self.error, self.current.as_mut()) {
+            let ply = partial.game.moves.len();
+            partial
+                .game
+                .comments
+                .push((ply, comment.trim().to_string()));
+        }
+    }
+
+    fn read_nag(&mut self, nag: u8) {
+        if let (None, Some(partial)) = (&self.error, self.current.as_mut()) {
+            partial.game.nags.push((partial.game.moves.len(), nag));
+        }
+    }
+
+    fn read_variation(&mut self, variation: &str) {
+        if let (None, Some(partial)) = (&self.error, self.current.as_mut()) {
+            let ply = partial.game.moves.len();
+            partial.game.variations.push((ply, variation.to_string()));
+        }
+    }
+
+    fn start_movetext(&mut self) {
+        self.in_movetext = true;
+        if self.current.is_none() && self.error.is_none() {
+            match PartialGame::new(mem::take(&mut self.tags)) {
+                Ok(mut partial) => {
+                    let leading = self.leading_comments.drain(..).map(|c| (0, c));
+                    partial.game.comments.extend(leading);
+                    self.current = Some(partial);
+                }
+                Err(e) => self.error = Some(e),
+            }
+        }
+    }
+
+    /// Read a move, move number or result
+    fn read_token(&mut self, token: &str) {
+        self.start_movetext();
+        if RESULTS.contains(&token) {
+            if let Some(partial) = self.current.as_mut() {
+                partial.game.set_tag("Result", token);
+            }
+            self.finish();
+            return;
+        }
+        let partial = match (&self.error, self.current.as_mut()) {
+            (None, Some(partial)) => partial,
+            _ => return,
+        };
+
+        // Move numbers, possibly written without a space before the move
+        let without_number = token.trim_start_matches(|c: char| c.is_ascii_digit());
+        let san = if without_number.starts_with('.') || without_number.is_empty() {
+            without_number.trim_start_matches('.')
+        } else {
+            token
+        };
+        if san.is_empty() {
+            return;
+        }
+        match parse_san(&partial.board, san) {
+            Some(m) => {
+                partial.board = partial.board.make_move_new(m);
+                partial.game.moves.push(m);
+            }
+            None => {
+                self.error = Some(format!(
+                    "Invalid move {} after {} moves",
+                    san,
+                    partial.game.moves.len()
+                ))
+            }
+        }
+    }
+
+    /// End the current game, if one was started
+    fn finish(&mut self) {
+        if !self.in_movetext && self.tags.is_empty() && self.error.is_none() {
+            return;
+        }
+        let tags = mem::take(&mut self.tags);
+        let current = self.current.take();
+        let game = match self.error.take() {
+            Some(e) => Err(e),
+            None => current.map_or_else(|| PartialGame::new(tags), Ok),
+        };
+        let number = self.games.len() + 1;
+        self.games.push(
+            game.map(|partial| partial.game)
+                .map_err(|e| format!("Game {}: {}", number, e)),
+        );
+        self.in_movetext = false;
+        self.leading_comments.clear();
+    }
+}
+
+/// Add a token to the text of a variation
+fn push_token(variation: &mut String, token: &str) {
+    if !variation.is_empty() && !variation.ends_with('(') {
+        variation.push(' ');
+    }
+    *variation += token;
+}
+
+/// Parse every game in `text`. Comments and NAGs on the main line are kept, and variations are
+/// kept as text. A game that can't be read is an error in its place, and doesn't affect the
+/// others.
+pub fn parse_pgn(text: &str) -> Vec<Result<Game, String>> {
+    let mut reader = PgnReader::default();
+    // How deeply nested in variations we are, and the text of the outermost one
+    let mut depth: usize = 0;
+    let mut variation = String::new();
+    let mut line_start = true;
+
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        let was_line_start = line_start;
+        line_start = c == '\n';
+        match c {
+            // Escaped lines are for other programs
+            '%' if was_line_start => {
+                read_until(&mut chars, '\n');
+                line_start = true;
+            }
+            ';' | '{' => {
+                let comment = read_until(&mut chars, if c == ';' { '\n' } else { '}' });
+                line_start = c == ';';
+                if depth == 0 {
+                    reader.read_comment(&comment);
+                } else {
+                    push_token(&mut variation, &format!("{{{}}}", comment.trim()));
+                }
+            }
+            '(' => {
+                if depth > 0 {
+                    push_token(&mut variation, "(");
+                }
+                depth += 1;
+            }
+            ')' if depth > 0 => {
+                depth -= 1;
+                if depth == 0 {
+                    reader.read_variation(&mem::take(&mut variation));
+                } else {
+                    variation.push(')');
+                }
+            }
+            ')' => {}
+            '[' if depth == 0 => reader.read_tag(&read_until(&mut chars, ']')),
+            _ if c.is_whitespace() => {}
+            _ => {
+                let mut token = c.to_string();
+                while let Some(&next) = chars.peek() {
+                    if next.is_whitespace() || "{}()[];".contains(next) {
+                        break;
+                    }
+                    token.push(next);
+                    chars.next();
+                }
+                if depth > 0 {
+                    push_token(&mut variation, &token);
+                } else if let Some(nag) = token.strip_prefix('$') {
+                    if let Ok(nag) = nag.parse() {
+                        reader.read_nag(nag);
+                    }
+                } else {
+                    reader.read_token(&token);
+                }
+            }
+        }
+    }
+
+    reader.finish();
+    reader.games
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_one(text: &str) -> Game {
+        let mut games = parse_pgn(text);
+        assert_eq!(games.len(), 1);
+        games.remove(0).unwrap()
+    }
+
+    fn moves(game: &Game) -> Vec<String> {
+        game.moves.iter().map(|m| m.to_string()).collect()
+    }
+
+    #[test]
+    fn move_numbers() {
+        let game = parse_one("1.e4 e5 2. Nf3 2...Nc6 3.Bb5 3... a6 12.Ba4 *");
+        assert_eq!(
+            moves(&game),
+            ["e2e4", "e7e5", "g1f3", "b8c6", "f1b5", "a7a6", "b5a4"]
+        );
+        assert_eq!(game.tag("Result"), Some("*"));
+    }
+
+    #[test]
+    fn variations_comments_and_nags() {
+        let game = parse_one(
+            "1. e4 {best by test} $1 (1. d4 {also good} (1. c4 $5) 1... d5) 1... e5 $2\n\
+             ; rest of line\n\
+             % escaped line 1. d4\n\
+             2. Nf3 {end} 1-0",
+        );
+        assert_eq!(moves(&game), ["e2e4", "e7e5", "g1f3"]);
+        assert_eq!(
+            game.comments,
+            [
+                (1, "best by test".to_string()),
+                (2, "rest of line".to_string()),
+                (3, "end".to_string())
+            ]
+        );
+        assert_eq!(game.nags, [(1, 1), (2, 2)]);
+        assert_eq!(
+            game.variations,
+            [(1, "1. d4 {also good} (1. c4 $5) 1... d5".to_string())]
+        );
+        assert_eq!(game.tag("Result"), Some("1-0"));
+    }
+
+    #[test]
+    fn annotations_round_trip() {
+        let text = "{opening} 1. e4 $1 {best by test} (1. d4 (1. c4 $5) 1... d5) 1... e5 $2 \
+                    2. Nf3 (2. f4 {gambit} exf4) {main line} 1-0\n\n\
+                    {next game} 1. d4 *";
+        let games: Vec<Game> = parse_pgn(text).into_iter().map(Result::unwrap).collect();
+        assert_eq!(games.len(), 2);
+        assert_eq!(games[0].comments[0], (0, "opening".to_string()));
+        assert_eq!(games[1].comments, [(0, "next game".to_string())]);
+
+        let written = games[0].to_pgn();
+        assert!(written.replace('\n', " ").contains(
+            "{opening} 1. e4 $1 {best by test} (1. d4 (1. c4 $5) 1... d5) 1... e5 $2 2. Nf3 \
+             {main line} (2. f4 {gambit} exf4) 1-0"
+        ));
+        let again = parse_one(&written);
+        assert_eq!(games[0].moves, again.moves);
+        assert_eq!(games[0].comments, again.comments);
+        assert_eq!(games[0].nags, again.nags);
+        assert_eq!(games[0].variations, again.variations);
+    }
+
+    #[test]
+    fn fen_start() {
+        let game = parse_one(
+            "[Event \"Endgame \\\"study\\\"\"]\n\
+             [SetUp \"1\"]\n\
+             [FEN \"8/8/8/4k3/8/8/8/R3K3 w Q - 0 40\"]\n\n\
+             40. Kd2 Kd5 41. Ra5+ *",
+        );
+        assert_eq!(game.fen.as_deref(), Some("8/8/8/4k3/8/8/8/R3K3 w Q - 0 40"));
+        assert_eq!(game.tag("Event"), Some("Endgame \"study\""));
+        assert_eq!(game.tag("FEN"), None);
+        assert_eq!(moves(&game), ["e1d2", "e5d5", "a1a5"]);
+        assert_eq!(game.history(2).1.len(), 3);
+    }
+
+    #[test]
+    fn round_trip() {
+        let text = "[Event \"Test\"]\n[White \"A\"]\n[Black \"B\"]\n[Result \"0-1\"]\n\n\
+                    {start} 1. f3 e5 2. g4 {blunder} Qh4# 0-1\n\n\
+                    [FEN \"4k3/8/8/8/8/8/8/4K2R b K - 3 10\"]\n\n\
+                    10... Kd7 11. O-O {castled} *\n";
+        let games: Vec<Game> = parse_pgn(text).into_iter().map(Result::unwrap).collect();
+        assert_eq!(games.len(), 2);
+        let written: String = games.iter().map(Game::to_pgn).collect();
+        assert!(written.contains("{start} 1. f3 e5 2. g4 {blunder} 2... Qh4# 0-1"));
+        assert!(written.contains("[FEN \"4k3/8/8/8/8/8/8/4K2R b K - 3 10\"]"));
+        assert!(written.contains("10... Kd7 11. O-O {castled} *"));
+
+        let reread: Vec<Game> = parse_pgn(&written)
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        for (game, again) in games.iter().zip(&reread) {
+            assert_eq!(game.moves, again.moves);
+            assert_eq!(game.comments, again.comments);
+            assert_eq!(game.fen, again.fen);
+            assert_eq!(game.tag("Result"), again.tag("Result"));
+        }
+    }
+
+    #[test]
+    fn bad_games_are_skipped() {
+        let games = parse_pgn(
+            "[Event \"Bad\"]\n\n1. e4 e5 2. Ke3 Nc6 1-0\n\n\
+             [Event \"Good\"]\n\n1. d4 d5 1/2-1/2\n\n\
+             [Event \"Bad FEN\"]\n[FEN \"not a fen\"]\n\n1. e4 *\n\n\
+             1. c4 *",
+        );
+        assert_eq!(games.len(), 4);
+        assert_eq!(
+            games[0].as_ref().unwrap_err(),
+            "Game 1: Invalid move Ke3 after 2 moves"
+        );
+        assert_eq!(games[1].as_ref().unwrap().tag("Event"), Some("Good"));
+        assert!(games[2].is_err());
+        assert_eq!(moves(games[3].as_ref().unwrap()), ["c2c4"]);
+    }
+}
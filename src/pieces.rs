@@ -0,0 +1,119 @@
+use chess::{
+    get_adjacent_files, get_bishop_moves, get_file, get_knight_moves, get_pawn_attacks, get_rank,
+    get_rook_moves, BitBoard, Board, Color, Piece, Square,
+};
+
+use crate::{
+    eval::Tapered,
+    pawns::{forward_ranks, relative_rank},
+};
+
+/// Value of each reachable square beyond the baseline, indexed by piece
+const MOBILITY: [Tapered; 6] = [
+    Tapered(0, 0),
+    Tapered(4, 4),
+    Tapered(5, 5),
+    Tapered(2, 4),
+    Tapered(1, 2),
+    Tapered(0, 0),
+];
+/// Roughly how many squares each piece reaches in an average position
+const MOBILITY_BASELINE: [i32; 6] = [0, 4, 6, 7, 13, 0];
+
+const ROOK_OPEN_FILE: Tapered = Tapered(25, 10);
+const ROOK_SEMI_OPEN_FILE: Tapered = Tapered(12, 6);
+const ROOK_ON_SEVENTH: Tapered = Tapered(20, 30);
+const KNIGHT_OUTPOST: Tapered = Tapered(20, 10);
+/// Per friendly pawn on the bishop's square color
+const BAD_BISHOP: Tapered = Tapered(-3, -5);
+
+const FILE_A: u64 = 0x0101010101010101;
+const FILE_H: u64 = 0x8080808080808080;
+const LIGHT_SQUARES: BitBoard = BitBoard(0x55AA55AA55AA55AA);
+
+/// Every square attacked by `color`'s pawns
+#[inline(always)]
+fn pawn_attacks(pawns: BitBoard, color: Color) -> BitBoard {
+    let (left, right) = (pawns.0 & !FILE_A, pawns.0 & !FILE_H);
+    match color {
+        Color::White => BitBoard(left << 7 | right << 9),
+        Color::Black => BitBoard(left >> 9 | right >> 7),
+    }
+}
+
+#[inline(always)]
+fn same_color_squares(square: Square) -> BitBoard {
+    if BitBoard::from_square(square) & LIGHT_SQUARES != BitBoard(0) {
+        LIGHT_SQUARES
+    } else {
+        !LIGHT_SQUARES
+    }
+}
+
+fn pieces(board: &Board, color: Color) -> Tapered {
+    let occupied = *board.combined();
+    let own = *board.color_combined(color);
+    let pawns = board.pieces(Piece::Pawn);
+    let own_pawns = pawns & own;
+    let enemy_pawns = pawns & board.color_combined(!color);
+    // Squares attacked by enemy pawns aren't really available
+    let area = !own & !pawn_attacks(enemy_pawns, !color);
+
+    let mut score = Tapered::default();
+    for piece in [Piece::Knight, Piece::Bishop, Piece::Rook, Piece::Queen] {
+        for square in board.pieces(piece) & own {
+            let attacks = match piece {
+                Piece::Knight => get_knight_moves(square),
+                Piece::Bishop => get_bishop_moves(square, occupied),
+                Piece::Rook => get_rook_moves(square, occupied),
+                _ => get_bishop_moves(square, occupied) | get_rook_moves(square, occupied),
+            };
+            let reachable = (attacks & area).popcnt() as i32;
+            score += MOBILITY[piece.to_index()] * (reachable - MOBILITY_BASELINE[piece.to_index()]);
+
+            match piece {
+                Piece::Knight => {
+                    // Defended by a pawn, and no enemy pawn can ever chase it away
+                    let supported = get_pawn_attacks(square, !color, own_pawns) != BitBoard(0);
+                    let chasers = enemy_pawns
+                        & get_adjacent_files(square.get_file())
+                        & forward_ranks(color, square.get_rank());
+                    if (3..=5).contains(&relative_rank(color, square))
+                        && supported
+                        && chasers == BitBoard(0)
+                    {
+                        score += KNIGHT_OUTPOST;
+                    }
+                }
+                Piece::Bishop => {
+                    let blockers = (own_pawns & same_color_squares(square)).popcnt() as i32;
+                    score += BAD_BISHOP * blockers;
+                }
+                Piece::Rook => {
+                    let file = get_file(square.get_file());
+                    if pawns & file == BitBoard(0) {
+                        score += ROOK_OPEN_FILE;
+                    } else if own_pawns & file == BitBoard(0) {
+                        score += ROOK_SEMI_OPEN_FILE;
+                    }
+
+                    // Only useful while there are pawns to attack or the king is cut off
+                    let enemy_king = board.king_square(!color);
+                    if relative_rank(color, square) == 6
+                        && (relative_rank(color, enemy_king) == 7
+                            || enemy_pawns & get_rank(square.get_rank()) != BitBoard(0))
+                    {
+                        score += ROOK_ON_SEVENTH;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    score
+}
+
+/// Mobility and piece placement from white's point of view
+pub fn evaluate_pieces(board: &Board) -> Tapered {
+    pieces(board, Color::White) - pieces(board, Color::Black)
+}
@@ -0,0 +1,237 @@
+use chess::{Board, BoardStatus, ChessMove, File, MoveGen, Piece, Rank, Square};
+
+#[inline(always)]
+fn piece_letter(piece: Piece) -> Option<char> {
+    match piece {
+        Piece::Pawn => None,
+        Piece::Knight => Some('N'),
+        Piece::Bishop => Some('B'),
+        Piece::Rook => Some('R'),
+        Piece::Queen => Some('Q'),
+        Piece::King => Some('K'),
+    }
+}
+
+#[inline(always)]
+fn letter_piece(letter: char) -> Option<Piece> {
+    match letter {
+        'N' => Some(Piece::Knight),
+        'B' => Some(Piece::Bishop),
+        'R' => Some(Piece::Rook),
+        'Q' => Some(Piece::Queen),
+        'K' => Some(Piece::King),
+        _ => None,
+    }
+}
+
+#[inline(always)]
+fn file_char(file: File) -> char {
+    (b'a' + file.to_index() as u8) as char
+}
+
+#[inline(always)]
+fn rank_char(rank: Rank) -> char {
+    (b'1' + rank.to_index() as u8) as char
+}
+
+/// The standard algebraic notation of `m`, a legal move on `board`
+pub fn to_san(board: &Board, m: ChessMove) -> String {
+    let piece = board.piece_on(m.get_source()).unwrap();
+    let (source, dest) = (m.get_source(), m.get_dest());
+    let mut san = String::new();
+
+    let file_distance = source.get_file().to_index() as i32 - dest.get_file().to_index() as i32;
+    if piece == Piece::King && file_distance.abs() == 2 {
+        san.push_str(if file_distance < 0 { "O-O" } else { "O-O-O" });
+    } else {
+        let capture = board.piece_on(dest).is_some()
+            || (piece == Piece::Pawn && source.get_file() != dest.get_file());
+        match piece_letter(piece) {
+            Some(letter) => {
+                san.push(letter);
+                // Name the source square only as far as needed to tell apart the other pieces
+                // that can go to the same square
+                let others: Vec<Square> = MoveGen::new_legal(board)
+                    .filter(|o| {
+                        o.get_dest() == dest
+                            && o.get_source() != source
+                            && board.piece_on(o.get_source()) == Some(piece)
+                    })
+                    .map(|o| o.get_source())
+                    .collect();
+                if !others.is_empty() {
+                    if others.iter().all(|s| s.get_file() != source.get_file()) {
+                        san.push(file_char(source.get_file()));
+                    } else if others.iter().all(|s| s.get_rank() != source.get_rank()) {
+                        san.push(rank_char(source.get_rank()));
+                    } else {
+                        san.push(file_char(source.get_file()));
+                        san.push(rank_char(source.get_rank()));
+                    }
+                }
+            }
+            None if capture => san.push(file_char(source.get_file())),
+            None => {}
+        }
+        if capture {
+            san.push('x');
+        }
+        san.push_str(&dest.to_string());
+        if let Some(promotion) = m.get_promotion().and_then(piece_letter) {
+            san.push('=');
+            san.push(promotion);
+        }
+    }
+
+    let after = board.make_move_new(m);
+    if after.status() == BoardStatus::Checkmate {
+        san.push('#');
+    } else if after.checkers().0 != 0 {
+        san.push('+');
+    }
+    san
+}
+
+/// Find the legal move on `board` written as `san`. Check marks, annotations and redundant
+/// disambiguation are accepted.
+pub fn parse_san(board: &Board, san: &str) -> Option<ChessMove> {
+    let san = san.trim_end_matches(['+', '#', '!', '?']);
+
+    let castle_file = match san {
+        "O-O" | "0-0" => Some(File::G),
+        "O-O-O" | "0-0-0" => Some(File::C),
+        _ => None,
+    };
+    if let Some(file) = castle_file {
+        let king = board.king_square(board.side_to_move());
+        return MoveGen::new_legal(board).find(|m| {
+            m.get_source() == king && m.get_dest() == Square::make_square(king.get_rank(), file)
+        });
+    }
+
+    let mut chars: Vec<char> = san.chars().filter(|&c| c != 'x' && c != '-').collect();
+    let piece = match chars.first().copied().and_then(letter_piece) {
+        Some(piece) => {
+            chars.remove(0);
+            piece
+        }
+        None => Piece::Pawn,
+    };
+    let promotion = match chars.last().copied().and_then(letter_piece) {
+        Some(promotion) if piece == Piece::Pawn => {
+            chars.pop();
+            if chars.last() == Some(&'=') {
+                chars.pop();
+            }
+            Some(promotion)
+        }
+        _ => None,
+    };
+
+    if chars.len() < 2 {
+        return None;
+    }
+    let dest: String = chars.split_off(chars.len() - 2).into_iter().collect();
+    let dest: Square = dest.parse().ok()?;
+    let mut from_file = None;
+    let mut from_rank = None;
+    for c in chars {
+        match c {
+            'a'..='h' => from_file = Some(File::from_index(c as usize - 'a' as usize)),
+            '1'..='8' => from_rank = Some(Rank::from_index(c as usize - '1' as usize)),
+            _ => return None,
+        }
+    }
+
+    let mut candidates = MoveGen::new_legal(board).filter(|m| {
+        m.get_dest() == dest
+            && m.get_promotion() == promotion
+            && board.piece_on(m.get_source()) == Some(piece)
+            && from_file.is_none_or(|f| m.get_source().get_file() == f)
+            && from_rank.is_none_or(|r| m.get_source().get_rank() == r)
+    });
+    let m = candidates.next()?;
+    // Ambiguous moves can't be trusted to be the intended one
+    candidates.next().is_none().then_some(m)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    fn san(fen: &str, m: &str) -> String {
+        to_san(
+            &Board::from_str(fen).unwrap(),
+            ChessMove::from_str(m).unwrap(),
+        )
+    }
+
+    fn parse(fen: &str, san: &str) -> Option<String> {
+        parse_san(&Board::from_str(fen).unwrap(), san).map(|m| m.to_string())
+    }
+
+    #[test]
+    fn round_trip() {
+        for fen in [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            "1k6/8/8/8/4Q2Q/8/8/2K4Q w - - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+        ] {
+            let board = Board::from_str(fen).unwrap();
+            for m in MoveGen::new_legal(&board) {
+                assert_eq!(parse_san(&board, &to_san(&board, m)), Some(m), "{}", fen);
+            }
+        }
+    }
+
+    #[test]
+    fn disambiguation() {
+        let knights = "4k3/8/8/8/8/5N2/8/1N2K3 w - - 0 1";
+        assert_eq!(san(knights, "b1d2"), "Nbd2");
+        assert_eq!(parse(knights, "Nd2"), None);
+        assert_eq!(parse(knights, "Nf3d2").as_deref(), Some("f3d2"));
+        let rooks = "4k3/8/8/R7/8/8/8/R3K3 w - - 0 1";
+        assert_eq!(san(rooks, "a1a3"), "R1a3");
+        let queens = "1k6/8/8/8/4Q2Q/8/8/2K4Q w - - 0 1";
+        assert_eq!(san(queens, "h4e1"), "Qh4e1");
+    }
+
+    #[test]
+    fn en_passant() {
+        let fen = "4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 2";
+        assert_eq!(san(fen, "e5d6"), "exd6");
+        assert_eq!(parse(fen, "exd6").as_deref(), Some("e5d6"));
+    }
+
+    #[test]
+    fn promotion() {
+        let fen = "2r1k3/1P6/8/8/8/8/8/4K3 w - - 0 1";
+        assert_eq!(san(fen, "b7b8q"), "b8=Q");
+        assert_eq!(san(fen, "b7c8n"), "bxc8=N");
+        assert_eq!(parse(fen, "b8Q").as_deref(), Some("b7b8q"));
+        assert_eq!(parse(fen, "bxc8=R").as_deref(), Some("b7c8r"));
+        assert_eq!(parse(fen, "b8"), None);
+    }
+
+    #[test]
+    fn castling() {
+        let fen = "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1";
+        assert_eq!(san(fen, "e1g1"), "O-O");
+        assert_eq!(san(fen, "e1c1"), "O-O-O");
+        assert_eq!(parse(fen, "0-0").as_deref(), Some("e1g1"));
+        assert_eq!(parse(fen, "O-O-O+").as_deref(), Some("e1c1"));
+    }
+
+    #[test]
+    fn checks_and_annotations() {
+        let fen = "r1bqkb1r/pppp1ppp/2n2n2/4p2Q/2B1P3/8/PPPP1PPP/RNB1K1NR w KQkq - 4 4";
+        assert_eq!(san(fen, "h5f7"), "Qxf7#");
+        assert_eq!(parse(fen, "Qxf7#!!").as_deref(), Some("h5f7"));
+        assert_eq!(parse(fen, "Qf7").as_deref(), Some("h5f7"));
+        assert_eq!(parse(fen, "Qf8"), None);
+    }
+}
@@ -1 +1,85 @@
 pub type ScoreTy = i16;
+
+/// Score of delivering checkmate at the root. Mates further away score closer to zero.
+pub const MATE: ScoreTy = 30000;
+/// Longest mate distance (in plies) that can be represented.
+const MAX_MATE_PLY: ScoreTy = 1000;
+/// Any score at least this far from zero is a forced mate.
+pub const MATE_BOUND: ScoreTy = MATE - MAX_MATE_PLY;
+
+#[inline(always)]
+pub fn mated_in(ply: usize) -> ScoreTy {
+    -MATE + ply as ScoreTy
+}
+
+#[inline(always)]
+pub fn is_mate(score: ScoreTy) -> bool {
+    score.abs() >= MATE_BOUND
+}
+
+/// Mate scores are relative to the root, but the transposition table needs them relative to the
+/// position being stored, since it can be reached at a different ply. Window bounds beyond
+/// [`MATE`] are left alone.
+#[inline(always)]
+pub fn to_tt(score: ScoreTy, ply: usize) -> ScoreTy {
+    if (MATE_BOUND..=MATE).contains(&score) {
+        score + ply as ScoreTy
+    } else if (-MATE..=-MATE_BOUND).contains(&score) {
+        score - ply as ScoreTy
+    } else {
+        score
+    }
+}
+
+#[inline(always)]
+pub fn from_tt(score: ScoreTy, ply: usize) -> ScoreTy {
+    if (MATE_BOUND..=MATE + MAX_MATE_PLY).contains(&score) {
+        score - ply as ScoreTy
+    } else if (-MATE - MAX_MATE_PLY..=-MATE_BOUND).contains(&score) {
+        score + ply as ScoreTy
+    } else {
+        score
+    }
+}
+
+/// Format a score the way UCI expects it, e.g. `cp 35` or `mate -2`
+pub fn format_score(score: ScoreTy) -> String {
+    if score >= MATE_BOUND {
+        format!("mate {}", (MATE - score + 1) / 2)
+    } else if score <= -MATE_BOUND {
+        format!("mate {}", -(MATE + score) / 2)
+    } else {
+        format!("cp {}", score)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mate_scores_move_with_ply() {
+        // Mated 5 plies from the root, found 3 plies in: 2 plies from the stored position
+        let stored = to_tt(mated_in(5), 3);
+        assert_eq!(stored, mated_in(2));
+        assert_eq!(from_tt(stored, 7), mated_in(9));
+        assert_eq!(from_tt(to_tt(MATE - 8, 6), 6), MATE - 8);
+        assert_eq!(from_tt(to_tt(-MATE + 8, 6), 6), -MATE + 8);
+    }
+
+    #[test]
+    fn other_scores_are_unchanged() {
+        for score in [0, 150, -150, MATE_BOUND - 1, -MATE_BOUND + 1] {
+            assert_eq!(to_tt(score, 10), score);
+            assert_eq!(from_tt(score, 10), score);
+        }
+    }
+
+    #[test]
+    fn format() {
+        assert_eq!(format_score(35), "cp 35");
+        assert_eq!(format_score(MATE - 1), "mate 1");
+        assert_eq!(format_score(MATE - 3), "mate 2");
+        assert_eq!(format_score(mated_in(2)), "mate -1");
+    }
+}
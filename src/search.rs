@@ -1,42 +1,405 @@
-use std::sync::atomic::Ordering;
+use std::{
+    cmp::Reverse,
+    mem,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, OnceLock,
+    },
+    thread,
+    time::Instant,
+};
 
-use chess::{Board, CacheTable, ChessMove, MoveGen};
+use chess::{Board, BoardStatus, ChessMove, MoveGen, Piece};
 use smallvec::{smallvec, SmallVec};
 
 use crate::{
-    eval::{evaluate, is_endgame},
-    helpers::{game_over, N_INF, P_INF},
-    move_sort::{sort_moves, sort_qs},
-    score::ScoreTy,
-    transposition::{CacheItem, Flag},
-    TIME_UP,
+    eval::{evaluate, has_non_pawn_material},
+    helpers::{captured_piece, format_pv, game_over, N_INF, P_INF},
+    move_sort::{sort_moves, sort_qs, CounterMoves, MoveHistory},
+    pawns::PawnTable,
+    score::{format_score, from_tt, is_mate, mated_in, to_tt, ScoreTy},
+    see::see,
+    time_manager::{TimeManager, DEFAULT_MOVE_OVERHEAD},
+    transposition::{CacheItem, Flag, TranspositionTable},
+    PONDERING, TIME_UP,
 };
 
-const R: u8 = 2;
+pub const NULL_MOVE_REDUCTION: u8 = 2;
 const DEPTH: usize = 12;
 pub const KILLER_MOVES: usize = 3;
+pub const MAX_PLY: usize = 128;
+/// Moves searched before quiet moves count as late
+const LATE_MOVES: usize = 3;
+/// Minimum depth for late move reductions
+const LMR_DEPTH: u8 = 3;
+/// Cap on how much a single cutoff changes a move's history score
+const MAX_HISTORY_BONUS: i32 = 1200;
+/// Half the width of the first aspiration window, in centipawns
+pub const ASPIRATION_WINDOW: ScoreTy = 25;
+/// Reverse futility pruning margin per ply of depth, in centipawns
+pub const FUTILITY_MARGIN: ScoreTy = 120;
+/// Shallower iterations are cheap and unstable, so they use a full window
+const ASPIRATION_DEPTH: u8 = 4;
+/// Late quiet moves searched at each shallow depth before the rest are pruned
+const LMP_MOVES: [usize; 4] = [0, 6, 10, 16];
+
+/// Search parameters that can be changed through UCI options, e.g. to test them in a match
+#[derive(Clone, Copy, Debug)]
+pub struct SearchParams {
+    pub null_move_reduction: u8,
+    pub aspiration_window: ScoreTy,
+    pub futility_margin: ScoreTy,
+}
+
+impl Default for SearchParams {
+    fn default() -> Self {
+        Self {
+            null_move_reduction: NULL_MOVE_REDUCTION,
+            aspiration_window: ASPIRATION_WINDOW,
+            futility_margin: FUTILITY_MARGIN,
+        }
+    }
+}
+
+/// How much to reduce the move at `index` in the move list at `depth`
+#[inline]
+fn reduction(depth: u8, index: usize) -> u8 {
+    static TABLE: OnceLock<[[u8; 64]; 64]> = OnceLock::new();
+    let table = TABLE.get_or_init(|| {
+        let mut table = [[0; 64]; 64];
+        for (d, row) in table.iter_mut().enumerate().skip(1) {
+            for (i, r) in row.iter_mut().enumerate().skip(1) {
+                *r = (0.75 + (d as f64).ln() * (i as f64).ln() / 2.25) as u8;
+            }
+        }
+        table
+    });
+    table[(depth as usize).min(63)][index.min(63)]
+}
+
+/// Conditions under which a search stops, whichever comes first.
+#[derive(Clone, Copy, Debug)]
+pub struct SearchLimits {
+    pub depth: u8,
+    pub nodes: Option<usize>,
+    pub time: TimeManager,
+}
+
+impl SearchLimits {
+    pub fn depth(depth: u8) -> Self {
+        Self {
+            depth,
+            nodes: None,
+            time: TimeManager::infinite(),
+        }
+    }
+}
+
+/// The outcome of a completed search iteration.
+#[derive(Clone, Debug)]
+pub struct SearchResult {
+    pub pv: Vec<ChessMove>,
+    pub score: ScoreTy,
+}
+
+impl SearchResult {
+    pub fn best_move(&self) -> ChessMove {
+        self.pv[0]
+    }
+}
+
+/// A position reached in the game or in the search tree, as needed for draw detection and
+/// countermoves.
+#[derive(Clone, Copy, Debug)]
+pub struct HistoryEntry {
+    pub hash: u64,
+    /// Plies since the last capture or pawn move
+    pub halfmove_clock: u16,
+    /// The move that reached this position, if known
+    pub last_move: Option<ChessMove>,
+}
+
+impl HistoryEntry {
+    pub fn new(board: &Board, halfmove_clock: u16) -> Self {
+        Self {
+            hash: board.get_hash(),
+            halfmove_clock,
+            last_move: None,
+        }
+    }
+
+    /// The entry for `new_board`, reached by playing `m` on `board`
+    #[inline]
+    pub fn after(&self, board: &Board, m: ChessMove, new_board: &Board) -> Self {
+        let irreversible = board.piece_on(m.get_dest()).is_some()
+            || board.piece_on(m.get_source()) == Some(Piece::Pawn);
+        Self {
+            hash: new_board.get_hash(),
+            halfmove_clock: if irreversible {
+                0
+            } else {
+                self.halfmove_clock + 1
+            },
+            last_move: Some(m),
+        }
+    }
+}
 
 pub struct Engine {
-    memo: CacheTable<CacheItem>,
+    memo: Arc<TranspositionTable>,
     killer_moves: SmallVec<[[Option<ChessMove>; KILLER_MOVES]; DEPTH]>,
+    /// Per thread, since pawn structure is cheap to recompute
+    pawns: PawnTable,
+    move_history: MoveHistory,
+    countermoves: CounterMoves,
     nodes_searched: usize,
     cached_timeup: bool,
+    limits: SearchLimits,
+    /// Whether the search is running on the opponent's time, so the clock doesn't count yet
+    pondering: bool,
+    /// Triangular PV table: `pv_table[ply]` holds the best line found from `ply` onward
+    pv_table: Vec<[ChessMove; MAX_PLY]>,
+    pv_length: [usize; MAX_PLY],
+    /// Deepest ply reached in the current iteration, counting extensions and quiescence
+    seldepth: usize,
+    /// Depth of the current iteration. Checks aren't extended past twice this many plies, so
+    /// perpetual checks can't extend forever.
+    root_depth: u8,
+    /// Positions from the start of the game up to the current node
+    history: Vec<HistoryEntry>,
+    root_color: chess::Color,
+    /// How much a draw is worth to the side that isn't to move at the root
+    pub contempt: ScoreTy,
+    pub params: SearchParams,
+    /// Number of best lines to search and report
+    pub multipv: usize,
+    /// Milliseconds reserved for communication with the GUI on every move
+    pub move_overhead: u64,
+    /// Whether to print `info` lines while searching
+    pub report: bool,
+    /// 0 for the main search thread, which reports results, and 1.. for helpers
+    thread_id: usize,
+    /// Engines for the other search threads, kept between searches
+    helpers: Vec<Engine>,
+    /// Set by the main thread when helpers should stop searching
+    stop: Arc<AtomicBool>,
+    /// Nodes searched by every thread, published periodically
+    node_counts: Arc<[AtomicUsize]>,
+    /// Nodes searched by the other threads, as of the last time they were read
+    other_nodes: usize,
 }
 
 impl Engine {
-    pub fn new(size: usize) -> Self {
+    /// Create an engine with a transposition table of `hash_mb` megabytes
+    pub fn new(hash_mb: usize) -> Self {
         Self {
-            memo: CacheTable::new(size, CacheItem::default()),
+            memo: Arc::new(TranspositionTable::new(hash_mb)),
             nodes_searched: 0,
             killer_moves: smallvec![[None; KILLER_MOVES]; DEPTH],
+            pawns: PawnTable::new(),
+            move_history: MoveHistory::new(),
+            countermoves: CounterMoves::new(),
             cached_timeup: TIME_UP.load(Ordering::SeqCst),
+            limits: SearchLimits::depth(u8::MAX - 1),
+            pondering: false,
+            pv_table: vec![[ChessMove::default(); MAX_PLY]; MAX_PLY],
+            pv_length: [0; MAX_PLY],
+            seldepth: 0,
+            root_depth: 0,
+            history: Vec::new(),
+            root_color: chess::Color::White,
+            contempt: 0,
+            params: SearchParams::default(),
+            multipv: 1,
+            move_overhead: DEFAULT_MOVE_OVERHEAD,
+            report: true,
+            thread_id: 0,
+            helpers: Vec::new(),
+            stop: Arc::new(AtomicBool::new(false)),
+            node_counts: Arc::new([AtomicUsize::new(0)]),
+            other_nodes: 0,
+        }
+    }
+
+    /// A helper thread's engine, sharing this one's transposition table
+    fn helper(&self, thread_id: usize) -> Self {
+        Self {
+            memo: Arc::clone(&self.memo),
+            nodes_searched: 0,
+            killer_moves: smallvec![[None; KILLER_MOVES]; DEPTH],
+            pawns: PawnTable::new(),
+            move_history: MoveHistory::new(),
+            countermoves: CounterMoves::new(),
+            cached_timeup: false,
+            limits: SearchLimits::depth(MAX_PLY as u8 - 1),
+            pondering: false,
+            pv_table: vec![[ChessMove::default(); MAX_PLY]; MAX_PLY],
+            pv_length: [0; MAX_PLY],
+            seldepth: 0,
+            root_depth: 0,
+            history: Vec::new(),
+            root_color: self.root_color,
+            contempt: self.contempt,
+            params: self.params,
+            multipv: 1,
+            move_overhead: self.move_overhead,
+            report: false,
+            thread_id,
+            helpers: Vec::new(),
+            stop: Arc::clone(&self.stop),
+            node_counts: Arc::clone(&self.node_counts),
+            other_nodes: 0,
+        }
+    }
+
+    pub fn threads(&self) -> usize {
+        self.helpers.len() + 1
+    }
+
+    /// Search on `threads` threads from now on. Helpers are created here rather than in
+    /// `best_move`, so allocating them doesn't use up search time.
+    pub fn set_threads(&mut self, threads: usize) {
+        let helpers = threads.max(1) - 1;
+        self.helpers.truncate(helpers);
+        while self.helpers.len() < helpers {
+            let helper = self.helper(self.helpers.len() + 1);
+            self.helpers.push(helper);
+        }
+    }
+
+    pub fn resize_hash(&mut self, hash_mb: usize) {
+        // Free the old table first, so both don't have to fit in memory at once
+        for helper in &mut self.helpers {
+            helper.memo = Arc::new(TranspositionTable::new(0));
+        }
+        self.memo = Arc::new(TranspositionTable::new(0));
+        self.memo = Arc::new(TranspositionTable::new(hash_mb));
+        for helper in &mut self.helpers {
+            helper.memo = Arc::clone(&self.memo);
         }
     }
 
+    /// Nodes searched by all threads
+    pub fn total_nodes(&self) -> usize {
+        self.nodes_searched + self.other_nodes
+    }
+
+    /// Forget everything learned from previous searches
+    pub fn new_game(&mut self) {
+        self.memo.clear();
+        self.clear_tables();
+        for helper in &mut self.helpers {
+            helper.clear_tables();
+        }
+    }
+
+    /// Clear this thread's move ordering and pawn tables
+    fn clear_tables(&mut self) {
+        self.killer_moves = smallvec![[None; KILLER_MOVES]; DEPTH];
+        self.pawns.clear();
+        self.move_history = MoveHistory::new();
+        self.countermoves = CounterMoves::new();
+    }
+
     #[inline]
-    fn quiesce(&mut self, board: Board, mut alpha: ScoreTy, beta: ScoreTy) -> ScoreTy {
+    fn push_history(&mut self, board: &Board, m: ChessMove, new_board: &Board) {
+        let entry = self.history.last().unwrap().after(board, m, new_board);
+        self.history.push(entry);
+    }
+
+    /// Whether the current node is drawn by repetition or the fifty-move rule
+    fn is_draw(&self, board: &Board) -> bool {
+        let current = *self.history.last().unwrap();
+        if current.halfmove_clock >= 100 {
+            return board.status() != BoardStatus::Checkmate;
+        }
+
+        // Only positions since the last irreversible move, with the same side to move, can repeat.
+        // Repeating one once is enough, even from before the root, since it could be repeated again.
+        self.history
+            .iter()
+            .rev()
+            .skip(2)
+            .step_by(2)
+            .take(current.halfmove_clock as usize / 2)
+            .any(|entry| entry.hash == current.hash)
+    }
+
+    #[inline]
+    fn draw_score(&self, board: &Board) -> ScoreTy {
+        if board.side_to_move() == self.root_color {
+            -self.contempt
+        } else {
+            self.contempt
+        }
+    }
+
+    #[inline]
+    fn time_up(&mut self) -> bool {
+        if self.cached_timeup {
+            return true;
+        }
+        if let Some(nodes) = self.limits.nodes {
+            self.cached_timeup = self.total_nodes() >= nodes;
+        }
+        if !self.cached_timeup && ((self.nodes_searched & 4095) == 0) {
+            self.publish_nodes();
+            self.cached_timeup = TIME_UP.load(Ordering::SeqCst)
+                || self.stop.load(Ordering::Relaxed)
+                || (!self.update_pondering() && self.limits.time.hard_stop());
+        }
+        self.cached_timeup
+    }
+
+    /// Whether we're still pondering. On `ponderhit`, the clock starts running.
+    fn update_pondering(&mut self) -> bool {
+        let pondering = PONDERING.load(Ordering::SeqCst);
+        if self.pondering && !pondering {
+            self.limits.time.restart();
+        }
+        self.pondering = pondering;
+        pondering
+    }
+
+    fn publish_nodes(&mut self) {
+        self.node_counts[self.thread_id].store(self.nodes_searched, Ordering::Relaxed);
+        self.other_nodes = self
+            .node_counts
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| i != self.thread_id)
+            .map(|(_, n)| n.load(Ordering::Relaxed))
+            .sum();
+    }
+
+    /// Make `m` followed by the line at `ply + 1` the new PV at `ply`
+    #[inline]
+    fn update_pv(&mut self, ply: usize, m: ChessMove) {
+        let child_length = self.pv_length[ply + 1];
+        let (parent, child) = self.pv_table.split_at_mut(ply + 1);
+        parent[ply][0] = m;
+        parent[ply][1..=child_length].copy_from_slice(&child[0][..child_length]);
+        self.pv_length[ply] = child_length + 1;
+    }
+
+    #[inline]
+    fn quiesce(&mut self, board: Board, mut alpha: ScoreTy, beta: ScoreTy, ply: usize) -> ScoreTy {
+        if self.time_up() {
+            return 0;
+        }
+
         self.nodes_searched += 1;
-        let standing_pat = evaluate(board);
+        self.seldepth = self.seldepth.max(ply);
+        let mut movegen = MoveGen::new_legal(&board);
+        // Counting the generated moves is cheap, unlike `Board::status`
+        if movegen.len() == 0 {
+            return if board.checkers().0 == 0 {
+                0
+            } else {
+                mated_in(ply)
+            };
+        }
+        let standing_pat = evaluate(board, &mut self.pawns);
         if standing_pat >= beta {
             return beta;
         }
@@ -44,7 +407,6 @@ impl Engine {
             alpha = standing_pat
         }
 
-        let mut movegen = MoveGen::new_legal(&board);
         let targets = board.color_combined(!board.side_to_move());
         // Filter down to attacking moves
         movegen.set_iterator_mask(*targets);
@@ -56,10 +418,13 @@ impl Engine {
         }
         sort_qs(&board, &mut possible_moves[..count]);
 
-        for i in 0..count {
-            let m = possible_moves[i];
+        for &m in &possible_moves[..count] {
+            // Losing captures are very unlikely to raise alpha
+            if see(&board, m) < 0 {
+                continue;
+            }
             let new_board = board.make_move_new(m);
-            let score = -self.quiesce(new_board, -beta, -alpha);
+            let score = -self.quiesce(new_board, -beta, -alpha, ply + 1);
             if score >= beta {
                 return beta;
             }
@@ -72,124 +437,221 @@ impl Engine {
     }
 
     #[inline]
-    #[allow(deprecated)]
+    #[allow(clippy::too_many_arguments)]
     fn pvs(
         &mut self,
-        start_depth: u8,
+        ply: usize,
         depth: u8,
         board: Board,
         mut alpha: ScoreTy,
         mut beta: ScoreTy,
-        pv: Option<ChessMove>,
+        pv: &[ChessMove],
         can_null: bool,
     ) -> ScoreTy {
-        if !self.cached_timeup && ((self.nodes_searched & 4095) == 0) {
-            self.cached_timeup = TIME_UP.load(Ordering::SeqCst);
-        }
-        if self.cached_timeup {
+        self.pv_length[ply] = 0;
+        self.seldepth = self.seldepth.max(ply);
+
+        if self.time_up() {
             return 0;
         }
 
+        // Extensions could otherwise run past the end of the PV table
+        if ply >= MAX_PLY - 1 {
+            return self.quiesce(board, alpha, beta, ply);
+        }
+
+        if self.is_draw(&board) {
+            return self.draw_score(&board);
+        }
+
         let orig_alpha = alpha;
-        let ply = (start_depth - depth) as usize;
+        let mut tt_move = None;
 
         if let Some(entry) = self.memo.get(board.get_hash()) {
+            tt_move = entry.best_move;
             if entry.depth >= depth {
+                let value = from_tt(entry.value, ply);
                 match entry.flag {
-                    Flag::Exact => return entry.value,
-                    Flag::LowerBound => alpha = ScoreTy::max(alpha, entry.value),
-                    Flag::UpperBound => beta = ScoreTy::max(beta, entry.value),
+                    Flag::Exact => return value,
+                    Flag::LowerBound => alpha = ScoreTy::max(alpha, value),
+                    Flag::UpperBound => beta = ScoreTy::min(beta, value),
                 }
 
                 if alpha >= beta {
-                    return entry.value;
+                    return value;
                 }
             }
         }
 
         self.nodes_searched += 1;
 
-        if depth == 0 || game_over(board) {
-            return self.quiesce(board, alpha, beta);
+        if depth == 0 {
+            return self.quiesce(board, alpha, beta, ply);
         }
 
         let not_checked = board.checkers().0 == 0;
-        let not_endgame = !is_endgame(board);
+        let not_endgame = has_non_pawn_material(&board, board.side_to_move());
+
+        // Before pruning, which could score a stalemate as a win. Counting is cheap, the moves are
+        // only listed if nothing gets pruned.
+        let movegen = MoveGen::new_legal(&board);
+        if movegen.len() == 0 {
+            return if not_checked { 0 } else { mated_in(ply) };
+        }
 
         // Null Move Pruning
-        if not_checked
-            && can_null
-            && depth > R
-            && (ScoreTy::abs(beta - 1) > N_INF + 100)
-            && not_endgame
-        {
-            let adapt_r = if depth > 6 { R + 1 } else { R };
+        let r = self.params.null_move_reduction;
+        if not_checked && can_null && depth > r && !is_mate(beta) && not_endgame {
+            let adapt_r = if depth > 6 { r + 1 } else { r };
             let nulled = board.null_move().unwrap();
+            // Passing is irreversible as far as repetitions are concerned
+            self.history.push(HistoryEntry::new(&nulled, 0));
             let score = -self.pvs(
-                start_depth,
+                ply + 1,
                 depth - 1 - adapt_r,
                 nulled,
                 -beta,
                 -beta + 1,
-                None,
+                &[],
                 false,
             );
+            self.history.pop();
             if score >= beta {
-                return score;
+                // A mate found after passing isn't proven
+                return if is_mate(score) { beta } else { score };
             }
         }
 
         // Reverse Futility Pruning
-        if depth < 3 && not_checked && (ScoreTy::abs(beta - 1) > N_INF + 100) {
-            let static_eval = evaluate(board);
+        if depth < 3 && not_checked && !is_mate(beta) {
+            let static_eval = evaluate(board, &mut self.pawns);
 
-            let eval_margin = 120 * depth as ScoreTy;
+            let eval_margin = self.params.futility_margin * depth as ScoreTy;
             if (static_eval - eval_margin) >= beta {
                 return static_eval - eval_margin;
             }
         }
 
         let mut possible_moves = [ChessMove::default(); 256];
-        let count = board.enumerate_moves(&mut possible_moves);
-        let killer_moves = self
-            .killer_moves
-            .get(ply as usize)
-            .unwrap_or(&[None; KILLER_MOVES]);
-        sort_moves(&board, &mut possible_moves[..count], killer_moves);
-        let mut is_pv = true;
-
-        for i in 0..count {
-            let m = possible_moves[i];
+        let mut count = 0;
+        for m in movegen {
+            possible_moves[count] = m;
+            count += 1;
+        }
+        let killer_moves = *self.killer_moves.get(ply).unwrap_or(&[None; KILLER_MOVES]);
+        let prev_move = self.history.last().unwrap().last_move;
+        let countermove = prev_move.and_then(|prev| self.countermoves.get(prev));
+        sort_moves(
+            &board,
+            &mut possible_moves[..count],
+            tt_move,
+            &killer_moves,
+            &self.move_history,
+            countermove,
+        );
+        // Follow the previous iteration's PV first
+        let pv_move = pv.first().and_then(|pv_move| {
+            possible_moves[..count]
+                .iter()
+                .position(|m| m == pv_move)
+                .map(|pos| possible_moves[..=pos].rotate_right(1))
+        });
+        let mut is_pv = pv_move.is_some();
+        let pv_node = beta as i32 - alpha as i32 > 1;
+        let mut best_move = None;
+        let mut quiets_searched: SmallVec<[ChessMove; 64]> = SmallVec::new();
+
+        for (i, &m) in possible_moves[..count].iter().enumerate() {
             let new_board = board.make_move_new(m);
-            let best_score = if Some(m) == pv && is_pv {
+            let quiet = captured_piece(&board, m).is_none() && m.get_promotion().is_none();
+            let late = i >= LATE_MOVES
+                && not_checked
+                && quiet
+                && new_board.checkers().0 == 0
+                && !killer_moves.contains(&Some(m));
+
+            // Late Move Pruning
+            if late
+                && !pv_node
+                && (depth as usize) < LMP_MOVES.len()
+                && i >= LMP_MOVES[depth as usize]
+                && !is_mate(alpha)
+            {
+                continue;
+            }
+
+            // Check Extension
+            let new_depth = if new_board.checkers().0 != 0 && ply < 2 * self.root_depth as usize {
+                depth
+            } else {
+                depth - 1
+            };
+
+            self.push_history(&board, m, &new_board);
+            let best_score = if is_pv {
                 is_pv = false;
-                -self.pvs(start_depth, depth - 1, new_board, -beta, -alpha, None, true)
+                -self.pvs(ply + 1, new_depth, new_board, -beta, -alpha, &pv[1..], true)
             } else {
+                // Late Move Reductions
+                let reduced_depth = if late && depth >= LMR_DEPTH {
+                    let r = reduction(depth, i).saturating_sub(pv_node as u8);
+                    new_depth.saturating_sub(r).max(1)
+                } else {
+                    new_depth
+                };
                 // Null Window Search
-                let s = -self.pvs(
-                    start_depth,
-                    depth - 1,
+                let mut s = -self.pvs(
+                    ply + 1,
+                    reduced_depth,
                     new_board,
                     -alpha - 1,
                     -alpha,
-                    None,
+                    &[],
                     true,
                 );
+                if reduced_depth < new_depth && s > alpha {
+                    s = -self.pvs(ply + 1, new_depth, new_board, -alpha - 1, -alpha, &[], true);
+                }
                 if alpha < s && s < beta {
-                    -self.pvs(start_depth, depth - 1, new_board, -beta, -s, None, true)
+                    -self.pvs(ply + 1, new_depth, new_board, -beta, -s, &[], true)
                 } else {
                     s
                 }
             };
-            alpha = ScoreTy::max(alpha, best_score);
+            self.history.pop();
+            if best_score > alpha {
+                alpha = best_score;
+                best_move = Some(m);
+                self.update_pv(ply, m);
+            }
             if alpha >= beta {
                 while self.killer_moves.len() <= ply {
                     self.killer_moves.push([None; KILLER_MOVES]);
                 }
                 self.killer_moves[ply].rotate_right(1);
                 self.killer_moves[ply][0] = Some(m);
+                if quiet {
+                    // Reward the move that refuted this position, and punish the ones that didn't
+                    let bonus = (depth as i32 * depth as i32).min(MAX_HISTORY_BONUS);
+                    let color = board.side_to_move();
+                    self.move_history.update(color, m, bonus);
+                    for &other in &quiets_searched {
+                        self.move_history.update(color, other, -bonus);
+                    }
+                    if let Some(prev) = prev_move {
+                        self.countermoves.set(prev, m);
+                    }
+                }
                 break;
             }
+            if quiet {
+                quiets_searched.push(m);
+            }
+        }
+
+        // Results of an aborted search are incomplete
+        if self.cached_timeup {
+            return 0;
         }
 
         let entry_flag = if alpha <= orig_alpha {
@@ -205,64 +667,290 @@ impl Engine {
             CacheItem {
                 depth,
                 flag: entry_flag,
-                value: alpha,
+                value: to_tt(alpha, ply),
+                // Keep the old move when no move raised alpha, it's still the best guess we have
+                best_move: best_move.or(tt_move),
             },
         );
 
         alpha
     }
 
+    /// The triangular PV is cut short by transposition table hits, so continue it with the moves
+    /// stored in the table, up to `depth` moves in total
+    fn complete_pv(&self, board: Board, depth: u8, pv: &mut Vec<ChessMove>) {
+        let mut board = pv.iter().fold(board, |b, &m| b.make_move_new(m));
+        while pv.len() < depth as usize {
+            match self.memo.get(board.get_hash()).and_then(|e| e.best_move) {
+                Some(m) if board.legal(m) => {
+                    pv.push(m);
+                    board = board.make_move_new(m);
+                }
+                _ => break,
+            }
+        }
+    }
+
+    /// Find the best line at the root within `alpha..beta`, ignoring the root moves in `excluded`.
+    /// Returns `None` if no move scores above `alpha`.
+    #[allow(clippy::too_many_arguments)]
     fn pvs_root(
         &mut self,
         depth: u8,
         board: Board,
-        pv: Option<ChessMove>,
-    ) -> Option<(ChessMove, ScoreTy)> {
-        let start_depth = depth;
+        pv: &[ChessMove],
+        excluded: &[ChessMove],
+        mut alpha: ScoreTy,
+        beta: ScoreTy,
+    ) -> Option<SearchResult> {
         if depth == 0 || game_over(board) {
             return None;
         }
 
-        let mut alpha = N_INF;
-        let beta = P_INF;
-
-        let possible_moves = MoveGen::new_legal(&board);
+        let mut possible_moves: SmallVec<[ChessMove; 64]> = MoveGen::new_legal(&board)
+            .filter(|m| !excluded.contains(m))
+            .collect();
+        if let Some(pos) = pv
+            .first()
+            .and_then(|pv_move| possible_moves.iter().position(|m| m == pv_move))
+        {
+            possible_moves[..=pos].rotate_right(1);
+        }
 
         let mut best_move = None;
-        for m in possible_moves {
+        for (i, &m) in possible_moves.iter().enumerate() {
             let new_board = board.make_move_new(m);
-            let score = -self.pvs(start_depth, depth - 1, new_board, -beta, -alpha, pv, true);
+            self.push_history(&board, m, &new_board);
+            let child_pv = if i == 0 && pv.first() == Some(&m) {
+                &pv[1..]
+            } else {
+                &[]
+            };
+            let new_depth = if new_board.checkers().0 != 0 {
+                depth
+            } else {
+                depth - 1
+            };
+            let score = -self.pvs(1, new_depth, new_board, -beta, -alpha, child_pv, true);
+            self.history.pop();
+            // The score of an interrupted search is meaningless
+            if self.cached_timeup {
+                break;
+            }
             if score > alpha {
                 alpha = score;
-                best_move = Some((m, alpha));
+                self.update_pv(0, m);
+                best_move = Some(SearchResult {
+                    pv: self.pv_table[0][..self.pv_length[0]].to_vec(),
+                    score: alpha,
+                });
+            }
+            if alpha >= beta {
+                break;
             }
         }
 
+        if best_move.is_none() && self.cached_timeup {
+            // Nothing finished, so guess with the transposition table, or the first move tried and
+            // the static evaluation
+            let tt_move = self
+                .memo
+                .get(board.get_hash())
+                .and_then(|e| Some((e.best_move?, e.value)))
+                .filter(|(m, _)| possible_moves.contains(m));
+            best_move = match tt_move {
+                Some(tt_move) => Some(tt_move),
+                None => possible_moves
+                    .first()
+                    .map(|&m| (m, evaluate(board, &mut self.pawns))),
+            }
+            .map(|(m, score)| SearchResult { pv: vec![m], score });
+        }
+
         best_move
     }
 
-    pub fn best_move(&mut self, max_depth: u8, board: Board) -> Option<(ChessMove, ScoreTy)> {
-        let mut best_move: Option<(ChessMove, ScoreTy)> = None;
+    /// Search the root with a narrow window around the previous iteration's score for this line,
+    /// widening it until the score falls inside
+    fn aspiration_search(
+        &mut self,
+        depth: u8,
+        board: Board,
+        prev: Option<&SearchResult>,
+        excluded: &[ChessMove],
+    ) -> Option<SearchResult> {
+        let prev_pv = prev.map_or(&[][..], |p| &p.pv[..]);
+        let mut delta = self.params.aspiration_window;
+        let (mut alpha, mut beta) = match prev {
+            Some(prev) if depth >= ASPIRATION_DEPTH && !is_mate(prev.score) => (
+                prev.score.saturating_sub(delta).max(N_INF),
+                prev.score.saturating_add(delta),
+            ),
+            _ => (N_INF, P_INF),
+        };
 
-        // Iterative Deepening
-        for depth in 1..(max_depth + 1) {
-            if !self.cached_timeup {
-                self.cached_timeup = TIME_UP.load(Ordering::SeqCst);
+        loop {
+            let result = self.pvs_root(depth, board, prev_pv, excluded, alpha, beta);
+            if self.cached_timeup {
+                return result;
+            }
+            delta = delta.saturating_mul(2);
+            match result {
+                Some(res) if res.score >= beta && beta < P_INF => {
+                    self.print_info(depth, excluded.len() + 1, &res, " lowerbound");
+                    beta = res.score.saturating_add(delta);
+                }
+                None if alpha > N_INF => {
+                    let bound = SearchResult {
+                        pv: prev_pv.to_vec(),
+                        score: alpha,
+                    };
+                    self.print_info(depth, excluded.len() + 1, &bound, " upperbound");
+                    alpha = alpha.saturating_sub(delta).max(N_INF);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Report `line` as the `k`th best line to the GUI. Helper threads never report.
+    fn print_info(&self, depth: u8, k: usize, line: &SearchResult, bound: &str) {
+        if !self.report {
+            return;
+        }
+        println!(
+            "info depth {} seldepth {} multipv {} score {}{} nodes {} time {} hashfull {} pv {}",
+            depth,
+            self.seldepth,
+            k,
+            format_score(line.score),
+            bound,
+            self.total_nodes(),
+            self.limits.time.elapsed().as_millis(),
+            self.memo.hashfull(),
+            format_pv(&line.pv)
+        );
+    }
+
+    /// Search `board`, which must be the last position in `history`, on `self.threads` threads.
+    pub fn best_move(
+        &mut self,
+        board: Board,
+        history: &[HistoryEntry],
+        limits: SearchLimits,
+    ) -> Option<SearchResult> {
+        self.memo.new_search();
+        self.stop = Arc::new(AtomicBool::new(false));
+        self.node_counts = (0..self.threads()).map(|_| AtomicUsize::new(0)).collect();
+        self.root_color = board.side_to_move();
+
+        // Lazy SMP: helpers search the same position, only sharing the transposition table
+        let mut helpers = mem::take(&mut self.helpers);
+        for helper in helpers.iter_mut() {
+            helper.stop = Arc::clone(&self.stop);
+            helper.node_counts = Arc::clone(&self.node_counts);
+            helper.root_color = self.root_color;
+            helper.contempt = self.contempt;
+            helper.params = self.params;
+        }
+        let stop = Arc::clone(&self.stop);
+        let result = thread::scope(|s| {
+            for helper in helpers.iter_mut() {
+                let limits = helper.limits;
+                s.spawn(move || helper.iterative_deepening(board, history, limits));
             }
+            let result = self.iterative_deepening(board, history, limits);
+            stop.store(true, Ordering::Relaxed);
+            result
+        });
+        self.helpers = helpers;
+        result
+    }
+
+    fn iterative_deepening(
+        &mut self,
+        board: Board,
+        history: &[HistoryEntry],
+        limits: SearchLimits,
+    ) -> Option<SearchResult> {
+        let mut lines: Vec<SearchResult> = Vec::new();
+        self.history = history.to_vec();
+        self.limits = limits;
+        self.pondering = PONDERING.load(Ordering::SeqCst);
+        self.nodes_searched = 0;
+        self.other_nodes = 0;
+        self.cached_timeup = false;
+
+        // Iterative Deepening
+        let max_depth = limits.depth.min(MAX_PLY as u8 - 1);
+        // Helpers start at different depths, so threads don't all search the same tree
+        for depth in (1 + (self.thread_id % 2) as u8)..(max_depth + 1) {
             if self.cached_timeup {
                 break;
             }
-            let pvs_res = self.pvs_root(depth, board, best_move.map(|(a, _)| a));
-            if let Some((_, new_analysis)) = pvs_res {
-                best_move = pvs_res;
-                println!(
-                    "info depth {} nodes {} score cp {}",
-                    depth, self.nodes_searched, new_analysis
-                );
+            let iteration_start = Instant::now();
+            self.seldepth = 0;
+            self.root_depth = depth;
+            let mut new_lines: Vec<SearchResult> = Vec::with_capacity(self.multipv);
+            // Each line is the best one among the root moves not yet covered by the others
+            while new_lines.len() < self.multipv {
+                let excluded: SmallVec<[ChessMove; 8]> =
+                    new_lines.iter().map(|l| l.best_move()).collect();
+                let prev = lines.iter().find(|l| !excluded.contains(&l.best_move()));
+                match self.aspiration_search(depth, board, prev, &excluded) {
+                    Some(mut res) => {
+                        self.complete_pv(board, depth, &mut res.pv);
+                        new_lines.push(res);
+                    }
+                    None => break,
+                }
+                if self.cached_timeup {
+                    break;
+                }
+            }
+            // An interrupted iteration can't be trusted, unless it's all we have
+            if self.cached_timeup && !lines.is_empty() {
+                break;
+            }
+            new_lines.sort_by_key(|l| Reverse(l.score));
+            self.publish_nodes();
+            for (k, line) in new_lines.iter().enumerate() {
+                self.print_info(depth, k + 1, line, "");
+            }
+            if !new_lines.is_empty() {
+                lines = new_lines;
+            }
+            if !self.update_pondering() && self.limits.time.soft_stop(iteration_start.elapsed()) {
+                break;
             }
-            self.nodes_searched = 0;
         }
 
-        best_move
+        lines.into_iter().next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn repetition_before_root() {
+        // Black is winning, but White can go back to the position before the last three moves
+        let mut board = Board::from_str("6k1/8/8/8/7q/7r/8/K7 b - - 0 1").unwrap();
+        let mut history = vec![HistoryEntry::new(&board, 0)];
+        for m in ["g8h8", "a1b1", "h8g8"] {
+            let m = ChessMove::from_str(m).unwrap();
+            let new_board = board.make_move_new(m);
+            history.push(history.last().unwrap().after(&board, m, &new_board));
+            board = new_board;
+        }
+
+        let result = Engine::new(1)
+            .best_move(board, &history, SearchLimits::depth(5))
+            .unwrap();
+        assert_eq!(result.best_move(), ChessMove::from_str("b1a1").unwrap());
+        assert_eq!(result.score, 0);
     }
 }
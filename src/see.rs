@@ -0,0 +1,179 @@
+use chess::{
+    get_bishop_moves, get_king_moves, get_knight_moves, get_pawn_attacks, get_rook_moves, BitBoard,
+    Board, ChessMove, Color, Piece, Square, ALL_PIECES,
+};
+
+use crate::{eval::get_score, score::ScoreTy};
+
+/// Pieces of both colors in `occupied` that attack `square`
+#[inline(always)]
+fn attackers_to(board: &Board, square: Square, occupied: BitBoard) -> BitBoard {
+    let pieces = |piece| *board.pieces(piece);
+    let white = board.color_combined(Color::White);
+    let black = board.color_combined(Color::Black);
+    let diagonal = pieces(Piece::Bishop) | pieces(Piece::Queen);
+    let straight = pieces(Piece::Rook) | pieces(Piece::Queen);
+
+    // A pawn attacks `square` if a pawn of the other color on `square` would attack it
+    (get_pawn_attacks(square, Color::Black, pieces(Piece::Pawn) & white)
+        | get_pawn_attacks(square, Color::White, pieces(Piece::Pawn) & black)
+        | get_knight_moves(square) & pieces(Piece::Knight)
+        | get_bishop_moves(square, occupied) & diagonal
+        | get_rook_moves(square, occupied) & straight
+        | get_king_moves(square) & pieces(Piece::King))
+        & occupied
+}
+
+/// Static exchange evaluation: the material balance after both sides keep recapturing on the
+/// destination of `m` with their least valuable piece, each stopping when it's no longer worth it.
+pub fn see(board: &Board, m: ChessMove) -> ScoreTy {
+    let target = m.get_dest();
+    let moving = board.piece_on(m.get_source()).unwrap();
+    let mut occupied = board.combined() ^ BitBoard::from_square(m.get_source());
+    let captured = match board.piece_on(target) {
+        Some(piece) => get_score(piece),
+        // En passant, where the captured pawn is beside the moving one
+        None if moving == Piece::Pawn && m.get_source().get_file() != target.get_file() => {
+            occupied ^= BitBoard::from_square(Square::make_square(
+                m.get_source().get_rank(),
+                target.get_file(),
+            ));
+            get_score(Piece::Pawn)
+        }
+        None => 0,
+    };
+
+    let mut gain = [0; 32];
+    gain[0] = captured;
+    let mut on_target = moving;
+    if let Some(promotion) = m.get_promotion() {
+        gain[0] += get_score(promotion) - get_score(Piece::Pawn);
+        on_target = promotion;
+    }
+
+    let mut side = !board.side_to_move();
+    let mut depth = 0;
+    loop {
+        let attackers = attackers_to(board, target, occupied);
+        let ours = attackers & board.color_combined(side);
+        let Some((piece, square)) = ALL_PIECES.iter().find_map(|&piece| {
+            (ours & board.pieces(piece))
+                .next()
+                .map(|square| (piece, square))
+        }) else {
+            break;
+        };
+        // The king can only take if nothing can take it back
+        if piece == Piece::King && attackers & board.color_combined(!side) != BitBoard(0) {
+            break;
+        }
+
+        depth += 1;
+        gain[depth] = get_score(on_target) - gain[depth - 1];
+        on_target = piece;
+        occupied ^= BitBoard::from_square(square);
+        side = !side;
+        if depth == gain.len() - 1 {
+            break;
+        }
+    }
+
+    // Either side can stand pat instead of recapturing
+    while depth > 0 {
+        gain[depth - 1] = -ScoreTy::max(-gain[depth - 1], gain[depth]);
+        depth -= 1;
+    }
+    gain[0]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    fn check(fen: &str, m: &str, expected: ScoreTy) {
+        let board = Board::from_str(fen).unwrap();
+        let m = ChessMove::from_str(m).unwrap();
+        assert!(board.legal(m), "{} is illegal in {}", m, fen);
+        assert_eq!(see(&board, m), expected, "{} in {}", m, fen);
+    }
+
+    #[test]
+    fn free_capture() {
+        check(
+            "4k3/8/8/3n4/4P3/8/8/4K3 w - - 0 1",
+            "e4d5",
+            get_score(Piece::Knight),
+        );
+    }
+
+    #[test]
+    fn losing_capture() {
+        check(
+            "4k3/8/2p5/3p4/8/8/8/3QK3 w - - 0 1",
+            "d1d5",
+            get_score(Piece::Pawn) - get_score(Piece::Queen),
+        );
+    }
+
+    #[test]
+    fn king_recapture() {
+        // The king takes back when nothing defends the queen
+        check(
+            "4k3/4p3/8/8/8/8/8/4Q1K1 w - - 0 1",
+            "e1e7",
+            get_score(Piece::Pawn) - get_score(Piece::Queen),
+        );
+        // but not when the rook behind it would take the king
+        check(
+            "4k3/4p3/8/8/8/8/4Q3/4R1K1 w - - 0 1",
+            "e2e7",
+            get_score(Piece::Pawn),
+        );
+    }
+
+    #[test]
+    fn x_ray() {
+        check(
+            "3r2k1/8/3p4/8/8/8/3R4/3R2K1 w - - 0 1",
+            "d2d6",
+            get_score(Piece::Pawn),
+        );
+        // Without the second rook, the first one is lost
+        check(
+            "3r2k1/8/3p4/8/8/8/3R4/6K1 w - - 0 1",
+            "d2d6",
+            get_score(Piece::Pawn) - get_score(Piece::Rook),
+        );
+    }
+
+    #[test]
+    fn promotion() {
+        check(
+            "2r3k1/1P6/8/8/8/8/8/6K1 w - - 0 1",
+            "b7c8q",
+            get_score(Piece::Rook) + get_score(Piece::Queen) - get_score(Piece::Pawn),
+        );
+        check(
+            "2rq2k1/1P6/8/8/8/8/8/6K1 w - - 0 1",
+            "b7c8q",
+            get_score(Piece::Rook) - get_score(Piece::Pawn),
+        );
+    }
+
+    #[test]
+    fn en_passant() {
+        check(
+            "6k1/8/8/3pP3/8/8/8/6K1 w - d6 0 2",
+            "e5d6",
+            get_score(Piece::Pawn),
+        );
+        // The captured pawn no longer blocks the rook behind it
+        check(
+            "3r2k1/8/8/3pP3/8/8/8/3R2K1 w - d6 0 2",
+            "e5d6",
+            get_score(Piece::Pawn),
+        );
+    }
+}
@@ -0,0 +1,144 @@
+/// Wins, losses and draws of one side of a match
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MatchScore {
+    pub wins: u32,
+    pub losses: u32,
+    pub draws: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SprtResult {
+    /// The change is at least `elo1` stronger
+    AcceptH1,
+    /// The change is at most `elo0` stronger
+    AcceptH0,
+    Continue,
+}
+
+/// Elo difference that gives an expected score of `score`
+fn elo_from_score(score: f64) -> f64 {
+    -400.0 * (1.0 / score - 1.0).log10()
+}
+
+/// Expected score for an Elo difference of `elo`
+fn score_from_elo(elo: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-elo / 400.0))
+}
+
+impl MatchScore {
+    pub fn games(&self) -> u32 {
+        self.wins + self.losses + self.draws
+    }
+
+    /// Mean score per game and its variance
+    fn mean_and_variance(&self) -> (f64, f64) {
+        let n = self.games() as f64;
+        let (w, l, d) = (
+            self.wins as f64 / n,
+            self.losses as f64 / n,
+            self.draws as f64 / n,
+        );
+        let mean = w + d / 2.0;
+        let variance = w * (1.0 - mean).powi(2) + l * mean.powi(2) + d * (0.5 - mean).powi(2);
+        (mean, variance)
+    }
+
+    /// Estimated Elo difference and the half width of its 95% confidence interval
+    pub fn elo(&self) -> (f64, f64) {
+        if self.games() == 0 {
+            return (0.0, 0.0);
+        }
+        let (mean, variance) = self.mean_and_variance();
+        let error = 1.96 * (variance / self.games() as f64).sqrt();
+        let low = elo_from_score((mean - error).max(1e-6));
+        let high = elo_from_score((mean + error).min(1.0 - 1e-6));
+        (
+            elo_from_score(mean.clamp(1e-6, 1.0 - 1e-6)),
+            (high - low) / 2.0,
+        )
+    }
+
+    /// Log-likelihood ratio of `elo1` against `elo0`, using the normal approximation of the
+    /// generalized SPRT
+    pub fn llr(&self, elo0: f64, elo1: f64) -> f64 {
+        if self.games() == 0 {
+            return 0.0;
+        }
+        let (mean, variance) = self.mean_and_variance();
+        if variance <= 0.0 {
+            return 0.0;
+        }
+        let (s0, s1) = (score_from_elo(elo0), score_from_elo(elo1));
+        (s1 - s0) * (2.0 * mean - s0 - s1) / (2.0 * variance) * self.games() as f64
+    }
+}
+
+/// Bounds on the log-likelihood ratio for error rates `alpha` and `beta`
+pub fn sprt_bounds(alpha: f64, beta: f64) -> (f64, f64) {
+    ((beta / (1.0 - alpha)).ln(), ((1.0 - beta) / alpha).ln())
+}
+
+pub fn sprt(llr: f64, bounds: (f64, f64)) -> SprtResult {
+    if llr >= bounds.1 {
+        SprtResult::AcceptH1
+    } else if llr <= bounds.0 {
+        SprtResult::AcceptH0
+    } else {
+        SprtResult::Continue
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn score(wins: u32, losses: u32, draws: u32) -> MatchScore {
+        MatchScore {
+            wins,
+            losses,
+            draws,
+        }
+    }
+
+    #[test]
+    fn bounds() {
+        let (lower, upper) = sprt_bounds(0.05, 0.05);
+        assert!((lower + 2.944).abs() < 1e-3);
+        assert!((upper - 2.944).abs() < 1e-3);
+        let (lower, upper) = sprt_bounds(0.05, 0.1);
+        assert!((lower + 2.251).abs() < 1e-3);
+        assert!((upper - 2.890).abs() < 1e-3);
+
+        assert_eq!(sprt(3.0, (lower, upper)), SprtResult::AcceptH1);
+        assert_eq!(sprt(-3.0, (lower, upper)), SprtResult::AcceptH0);
+        assert_eq!(sprt(0.0, (lower, upper)), SprtResult::Continue);
+    }
+
+    #[test]
+    fn llr() {
+        assert_eq!(score(0, 0, 0).llr(0.0, 5.0), 0.0);
+        // No variance, no information
+        assert_eq!(score(0, 0, 100).llr(0.0, 5.0), 0.0);
+
+        let winning = score(600, 400, 1000).llr(0.0, 5.0);
+        assert!(winning > 2.944);
+        assert!(score(400, 600, 1000).llr(0.0, 5.0) < -2.944);
+        // Around hypotheses centred on 0, a result and its mirror image are equally strong
+        let symmetric = |s: MatchScore| s.llr(-5.0, 5.0);
+        assert!((symmetric(score(400, 600, 1000)) + symmetric(score(600, 400, 1000))).abs() < 1e-9);
+        // More games with the same results are more certain
+        assert!(score(1200, 800, 2000).llr(0.0, 5.0) > winning);
+        // Halfway between the hypotheses favours neither
+        assert!(symmetric(score(1000, 1000, 0)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn elo() {
+        assert_eq!(score(0, 0, 0).elo(), (0.0, 0.0));
+        let (elo, error) = score(50, 50, 100).elo();
+        assert!(elo.abs() < 1e-9);
+        assert!(error > 0.0);
+        let (elo, _) = score(75, 25, 0).elo();
+        assert!((elo - 190.85).abs() < 0.01);
+    }
+}
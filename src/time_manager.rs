@@ -0,0 +1,158 @@
+use std::time::{Duration, Instant};
+
+/// Default time reserved for communication with the GUI, in milliseconds.
+pub const DEFAULT_MOVE_OVERHEAD: u64 = 50;
+/// Number of moves assumed to be left when the GUI doesn't send `movestogo`.
+const DEFAULT_MOVES_TO_GO: u64 = 30;
+/// How many times longer the next iteration is assumed to take than the last one.
+const BRANCHING_FACTOR: u32 = 2;
+/// The move overhead never takes more than this fraction of the time we have.
+const MAX_OVERHEAD_FRACTION: u64 = 4;
+
+#[derive(Clone, Copy, Debug)]
+pub struct TimeManager {
+    start: Instant,
+    /// Don't start another iteration after this much time has passed.
+    soft: Option<Duration>,
+    /// Abort the search, even in the middle of an iteration, after this much time has passed.
+    hard: Option<Duration>,
+}
+
+/// Keep short searches from being eaten up entirely by the overhead
+#[inline]
+fn cap_overhead(time: u64, overhead: u64) -> u64 {
+    overhead.min(time / MAX_OVERHEAD_FRACTION)
+}
+
+impl Default for TimeManager {
+    fn default() -> Self {
+        Self::infinite()
+    }
+}
+
+impl TimeManager {
+    pub fn infinite() -> Self {
+        Self {
+            start: Instant::now(),
+            soft: None,
+            hard: None,
+        }
+    }
+
+    /// Search for exactly `movetime` milliseconds, minus `overhead`.
+    pub fn from_movetime(movetime: u64, overhead: u64) -> Self {
+        let movetime = Duration::from_millis(
+            movetime
+                .saturating_sub(cap_overhead(movetime, overhead))
+                .max(1),
+        );
+        Self {
+            start: Instant::now(),
+            soft: Some(movetime),
+            hard: Some(movetime),
+        }
+    }
+
+    /// Budget time for a move given the remaining clock time, increment and moves until the next
+    /// time control (all in milliseconds), keeping `overhead` milliseconds in reserve.
+    pub fn from_clock(time_left: u64, inc: u64, moves_to_go: Option<u64>, overhead: u64) -> Self {
+        let available = time_left
+            .saturating_sub(cap_overhead(time_left, overhead))
+            .max(1);
+        let moves_to_go = moves_to_go.unwrap_or(DEFAULT_MOVES_TO_GO).max(1);
+
+        // Never plan to use the entire clock, leave something for the next moves
+        let max_usable = if moves_to_go == 1 {
+            available * 9 / 10
+        } else {
+            available * 4 / 5
+        }
+        .max(1);
+        let soft = (available / moves_to_go + inc * 3 / 4).min(max_usable);
+        let hard = (soft * 3).min(max_usable);
+
+        Self {
+            start: Instant::now(),
+            soft: Some(Duration::from_millis(soft)),
+            hard: Some(Duration::from_millis(hard)),
+        }
+    }
+
+    /// Start counting from now, e.g. when a ponder search becomes a real one
+    pub fn restart(&mut self) {
+        self.start = Instant::now();
+    }
+
+    #[inline]
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    /// Whether the search must be aborted immediately.
+    #[inline]
+    pub fn hard_stop(&self) -> bool {
+        self.hard.is_some_and(|hard| self.elapsed() >= hard)
+    }
+
+    /// Whether a new iteration should be started, given how long the last one took.
+    pub fn soft_stop(&self, last_iteration: Duration) -> bool {
+        let elapsed = self.elapsed();
+        if self.soft.is_some_and(|soft| elapsed >= soft) {
+            return true;
+        }
+        // Don't start an iteration that won't be able to finish anyway
+        self.hard
+            .is_some_and(|hard| elapsed + last_iteration * BRANCHING_FACTOR >= hard)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn millis(limit: Option<Duration>) -> u64 {
+        limit.unwrap().as_millis() as u64
+    }
+
+    #[test]
+    fn clock_budget() {
+        // A 30th of what's left after the overhead, with up to 3 times that before aborting
+        let time = TimeManager::from_clock(60_000, 0, None, 50);
+        assert_eq!(millis(time.soft), 1998);
+        assert_eq!(millis(time.hard), 5994);
+
+        // Most of the increment is spent too
+        let time = TimeManager::from_clock(10_000, 1000, None, 0);
+        assert_eq!(millis(time.soft), 1083);
+        assert_eq!(millis(time.hard), 3249);
+    }
+
+    #[test]
+    fn clock_keeps_a_reserve() {
+        let time = TimeManager::from_clock(1000, 0, Some(1), 50);
+        assert_eq!(millis(time.soft), 855);
+        assert_eq!(millis(time.hard), 855);
+
+        // The overhead can't eat the whole clock
+        let time = TimeManager::from_clock(100, 0, Some(1), 500);
+        assert_eq!(millis(time.hard), 67);
+        for time_left in [1, 10, 100, 1000] {
+            assert!(millis(TimeManager::from_clock(time_left, 0, None, 50).hard) < time_left);
+        }
+    }
+
+    #[test]
+    fn soft_stop() {
+        let mut time = TimeManager::from_clock(60_000, 0, None, 0);
+        time.soft = Some(Duration::from_secs(1));
+        time.hard = Some(Duration::from_secs(3));
+        assert!(!time.soft_stop(Duration::ZERO));
+        // The next iteration would run past the hard limit
+        assert!(time.soft_stop(Duration::from_millis(1600)));
+
+        time.start = Instant::now() - Duration::from_secs(1);
+        assert!(time.soft_stop(Duration::ZERO));
+        assert!(!time.hard_stop());
+        assert!(!TimeManager::infinite().soft_stop(Duration::from_secs(1000)));
+    }
+}
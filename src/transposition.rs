@@ -1,21 +1,271 @@
+use std::{
+    mem,
+    sync::atomic::{AtomicU64, AtomicU8, Ordering},
+};
+
+use chess::{ChessMove, ALL_PIECES, ALL_SQUARES};
+
 use crate::score::ScoreTy;
 
-#[derive(PartialEq, PartialOrd, Clone, Copy, Debug)]
+pub const DEFAULT_HASH_MB: usize = 16;
+
+#[derive(PartialEq, PartialOrd, Clone, Copy, Debug, Default)]
 pub enum Flag {
     Exact,
+    #[default]
     LowerBound,
     UpperBound,
 }
 
-impl Default for Flag {
-    fn default() -> Self {
-        Flag::LowerBound
-    }
-}
-
 #[derive(PartialEq, PartialOrd, Clone, Copy, Debug, Default)]
 pub struct CacheItem {
     pub depth: u8,
     pub flag: Flag,
     pub value: ScoreTy,
+    pub best_move: Option<ChessMove>,
+}
+
+// Layout of an entry packed into a u64
+const VALUE_SHIFT: u32 = 0;
+const DEPTH_SHIFT: u32 = 16;
+const FLAG_SHIFT: u32 = 24;
+const MOVE_SHIFT: u32 = 26;
+const GENERATION_SHIFT: u32 = 42;
+
+fn pack_move(m: Option<ChessMove>) -> u64 {
+    m.map_or(0, |m| {
+        let promotion = m.get_promotion().map_or(0, |p| p.to_index() as u64 + 1);
+        1 << 15
+            | promotion << 12
+            | (m.get_source().to_index() as u64) << 6
+            | m.get_dest().to_index() as u64
+    })
+}
+
+fn unpack_move(bits: u64) -> Option<ChessMove> {
+    if bits & (1 << 15) == 0 {
+        return None;
+    }
+    let promotion = match (bits >> 12) & 0b111 {
+        0 => None,
+        p => Some(ALL_PIECES[p as usize - 1]),
+    };
+    let source = ALL_SQUARES[((bits >> 6) & 0b111111) as usize];
+    let dest = ALL_SQUARES[(bits & 0b111111) as usize];
+    Some(ChessMove::new(source, dest, promotion))
+}
+
+fn pack(item: CacheItem, generation: u8) -> u64 {
+    let flag = match item.flag {
+        Flag::Exact => 0,
+        Flag::LowerBound => 1,
+        Flag::UpperBound => 2,
+    };
+    (item.value as u16 as u64) << VALUE_SHIFT
+        | (item.depth as u64) << DEPTH_SHIFT
+        | flag << FLAG_SHIFT
+        | pack_move(item.best_move) << MOVE_SHIFT
+        | (generation as u64) << GENERATION_SHIFT
+}
+
+fn unpack(data: u64) -> CacheItem {
+    CacheItem {
+        depth: (data >> DEPTH_SHIFT) as u8,
+        flag: match (data >> FLAG_SHIFT) & 0b11 {
+            0 => Flag::Exact,
+            1 => Flag::LowerBound,
+            _ => Flag::UpperBound,
+        },
+        value: (data >> VALUE_SHIFT) as u16 as ScoreTy,
+        best_move: unpack_move((data >> MOVE_SHIFT) & 0xffff),
+    }
+}
+
+#[inline(always)]
+fn generation_of(data: u64) -> u8 {
+    (data >> GENERATION_SHIFT) as u8
+}
+
+/// A lock-free slot. The key is stored xored with the data, so an entry torn by a concurrent
+/// write fails verification instead of returning another position's data.
+#[derive(Default)]
+struct Slot {
+    key: AtomicU64,
+    data: AtomicU64,
+}
+
+impl Slot {
+    /// The data stored for `hash`, if any. Empty slots have all-zero data.
+    #[inline]
+    fn load(&self, hash: u64) -> Option<u64> {
+        let data = self.data.load(Ordering::Relaxed);
+        let key = self.key.load(Ordering::Relaxed) ^ data;
+        (data != 0 && key == hash).then_some(data)
+    }
+
+    #[inline]
+    fn store(&self, hash: u64, data: u64) {
+        self.key.store(hash ^ data, Ordering::Relaxed);
+        self.data.store(data, Ordering::Relaxed);
+    }
+}
+
+/// Each bucket keeps the deepest recent result, plus whatever was stored last
+#[derive(Default)]
+struct Bucket {
+    depth_preferred: Slot,
+    always_replace: Slot,
+}
+
+/// A transposition table that can be shared between search threads
+pub struct TranspositionTable {
+    buckets: Vec<Bucket>,
+    /// Incremented for every search, so results of old searches get replaced first
+    generation: AtomicU8,
+}
+
+impl TranspositionTable {
+    pub fn new(size_mb: usize) -> Self {
+        let count = (size_mb * 1024 * 1024 / mem::size_of::<Bucket>()).max(1);
+        Self {
+            buckets: (0..count).map(|_| Bucket::default()).collect(),
+            generation: AtomicU8::new(1),
+        }
+    }
+
+    pub fn clear(&self) {
+        for bucket in &self.buckets {
+            bucket.depth_preferred.store(0, 0);
+            bucket.always_replace.store(0, 0);
+        }
+        self.generation.store(1, Ordering::Relaxed);
+    }
+
+    pub fn new_search(&self) {
+        // Generation 0 is reserved for empty entries
+        let generation = self
+            .generation
+            .load(Ordering::Relaxed)
+            .wrapping_add(1)
+            .max(1);
+        self.generation.store(generation, Ordering::Relaxed);
+    }
+
+    #[inline]
+    fn bucket(&self, hash: u64) -> &Bucket {
+        &self.buckets[((hash as u128 * self.buckets.len() as u128) >> 64) as usize]
+    }
+
+    #[inline]
+    pub fn get(&self, hash: u64) -> Option<CacheItem> {
+        let bucket = self.bucket(hash);
+        bucket
+            .depth_preferred
+            .load(hash)
+            .or_else(|| bucket.always_replace.load(hash))
+            .map(unpack)
+    }
+
+    #[inline]
+    pub fn add(&self, hash: u64, item: CacheItem) {
+        let generation = self.generation.load(Ordering::Relaxed);
+        let bucket = self.bucket(hash);
+        let data = pack(item, generation);
+
+        let preferred = bucket.depth_preferred.data.load(Ordering::Relaxed);
+        if bucket.depth_preferred.load(hash).is_some()
+            || generation_of(preferred) != generation
+            || item.depth >= unpack(preferred).depth
+        {
+            bucket.depth_preferred.store(hash, data);
+        } else {
+            bucket.always_replace.store(hash, data);
+        }
+    }
+
+    /// How full the table is with entries from the current search, in permille
+    pub fn hashfull(&self) -> usize {
+        let generation = self.generation.load(Ordering::Relaxed);
+        let sample = &self.buckets[..self.buckets.len().min(500)];
+        let used = sample
+            .iter()
+            .flat_map(|b| [&b.depth_preferred, &b.always_replace])
+            .filter(|s| generation_of(s.data.load(Ordering::Relaxed)) == generation)
+            .count();
+        used * 1000 / (sample.len() * 2)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn pack_round_trip() {
+        let items = [
+            CacheItem {
+                depth: 0,
+                flag: Flag::Exact,
+                value: 0,
+                best_move: None,
+            },
+            CacheItem {
+                depth: 255,
+                flag: Flag::LowerBound,
+                value: -30000,
+                best_move: Some(ChessMove::from_str("h7h8n").unwrap()),
+            },
+            CacheItem {
+                depth: 12,
+                flag: Flag::UpperBound,
+                value: 1234,
+                best_move: Some(ChessMove::from_str("a1h8").unwrap()),
+            },
+        ];
+        for item in items {
+            let data = pack(item, 200);
+            assert_eq!(unpack(data), item);
+            assert_eq!(generation_of(data), 200);
+        }
+    }
+
+    #[test]
+    fn torn_entries_are_rejected() {
+        let first = pack(CacheItem::default(), 1);
+        let second = pack(
+            CacheItem {
+                depth: 5,
+                ..CacheItem::default()
+            },
+            1,
+        );
+        let slot = Slot::default();
+        assert_eq!(slot.load(0), None);
+        slot.store(42, first);
+        assert_eq!(slot.load(42), Some(first));
+        assert_eq!(slot.load(43), None);
+
+        // Another thread's data written over this key
+        slot.data.store(second, Ordering::Relaxed);
+        assert_eq!(slot.load(42), None);
+        assert_eq!(slot.load(43), None);
+    }
+
+    #[test]
+    fn table_get_and_add() {
+        let table = TranspositionTable::new(1);
+        let item = CacheItem {
+            depth: 3,
+            flag: Flag::Exact,
+            value: -50,
+            best_move: Some(ChessMove::from_str("e2e4").unwrap()),
+        };
+        table.add(7, item);
+        assert_eq!(table.get(7), Some(item));
+        assert_eq!(table.get(8), None);
+        table.clear();
+        assert_eq!(table.get(7), None);
+    }
 }